@@ -1,161 +1,442 @@
 use anyhow::{Context, Result};
-use quick_xml::events::{BytesText, Event};
-use quick_xml::reader::Reader;
-use quick_xml::Writer;
-use std::fs::File;
-use std::io::{BufReader, BufWriter};
+use chrono::{DateTime, Utc};
+use quick_xml::events::{BytesEnd, BytesStart, BytesText, Event};
+use std::io::{BufRead, Write};
 
+use crate::date::format_date;
+use crate::element::Element;
+use crate::header::Header;
+use crate::io::{create_output, open_input, stdin_to_temp, STDIO};
+use crate::namespace::Namespaces;
+use crate::quarantine::Quarantine;
+use crate::reader::{TmxItem, TmxReader};
+use crate::t5n::{convert, T5nMode};
+use crate::tu::Tu;
 use crate::whitespace::is_xml_whitespace;
-use crate::attribute::get_attribute_value;
-
-fn write_t5_n_tag(writer: &mut Writer<BufWriter<File>>, e: &quick_xml::events::BytesStart, unprotect: bool) -> Result<()> {
-    if ! unprotect {
-        writer.write_event(Event::Empty(e.clone()))?;
-    } else if let Some(n_value) = get_attribute_value(&e, b"n") {
-        let n_str = String::from_utf8_lossy(&n_value);
-        writer.write_event(Event::Text(BytesText::from_escaped(n_str.as_ref())))?;
+use crate::writer::TmxWriter;
+
+#[derive(Clone, Debug, Default)]
+pub struct ConcatOptions {
+    /// Replace `<t5:n>` placeholders, and those of the other namespaces in `namespaces`, with their value.
+    pub unprotect: bool,
+    /// Which inline elements are placeholders.
+    pub namespaces: Namespaces,
+    /// Merge files with different `srclang`s instead of failing; the merged header gets `srclang="*all*"`.
+    pub mixed_srclang: bool,
+    /// Describe the merge in the header: `creationtool`, `creationtoolversion` and this `creationdate`.
+    pub regenerate_header: Option<DateTime<Utc>>,
+    /// Put malformed TUs there and go on instead of failing.
+    pub quarantine: Option<Quarantine>,
+}
+
+fn write_tu<W: Write>(writer: &mut TmxWriter<W>, tu: &Tu, options: &ConcatOptions, line_ending: &str) -> Result<()> {
+    let mut unprotected;
+    let tu = if options.unprotect {
+        unprotected = tu.clone();
+        convert(&mut unprotected, T5nMode::N, &options.namespaces);
+        &unprotected
     } else {
-        writer.write_event(Event::Empty(e.clone()))?;
+        tu
+    };
+
+    let mut seg_depth = 0;
+    for ev in &tu.events {
+        match ev {
+            Event::Start(e) if e.name().as_ref() == b"seg" => {
+                seg_depth += 1;
+                writer.write_event(ev.borrow())?;
+            }
+            Event::End(e) if e.name().as_ref() == b"seg" => {
+                seg_depth -= 1;
+                writer.write_event(ev.borrow())?;
+            }
+            // indentation, but not the whitespace content of a segment
+            Event::Text(t) if seg_depth == 0 => writer.write_event(Event::Text(with_line_ending(t, line_ending)))?,
+            _ => writer.write_event(ev.borrow())?,
+        }
     }
 
     Ok(())
 }
 
-/// Concatenate TMX files by merging all <tu> nodes from subsequent files into the first one's <body>.
-pub fn concat(files: &[String], output: &String, unprotect: bool) -> Result<()> {
-    if files.is_empty() {
-        return Err(anyhow::anyhow!("No input files provided"));
+// line breaks in whitespace-only text are rewritten to `line_ending`, any other text is kept
+fn with_line_ending(t: &BytesText, line_ending: &str) -> BytesText<'static> {
+    if !is_xml_whitespace(t) {
+        return t.clone().into_owned();
     }
 
-    // Open first file and parse header, <body>, and <tu> nodes
-    let first_file = File::open(&files[0]).context(format!("Failed to open first input file {}", &files[0]))?;
-    let mut reader = Reader::from_reader(BufReader::new(first_file));
-    reader.config_mut().trim_text(false);
+    let text = String::from_utf8_lossy(t).replace("\r\n", "\n").replace('\r', "\n");
+    BytesText::from_escaped(text.replace('\n', line_ending))
+}
 
-    let output_file = File::create(output).context(format!("Failed to create output file {}", output))?;
-    let mut writer = Writer::new(BufWriter::new(output_file));
+fn is_body(ev: &Event) -> bool {
+    matches!(ev, Event::Start(e) | Event::Empty(e) if e.name().as_ref() == b"body")
+}
 
-    let mut buf = Vec::new();
-    let mut in_body = false;
+/// Writes the content of the merged `<body>`.
+///
+/// Whitespace is held back until the next element, so the indentation in front of each file's `</body>` is dropped
+/// and only the first file's is written, once, in front of the final `</body>`.
+struct BodyWriter<'a, W: Write> {
+    writer: &'a mut TmxWriter<W>,
+    options: &'a ConcatOptions,
+    line_ending: String,
+    /// The first file's `<body/>`, opened when another file has content.
+    unopened: Option<BytesStart<'static>>,
+    pending: Option<BytesText<'static>>,
+}
 
-    // Write header and <body> start from first file
-    loop {
-        match reader.read_event_into(&mut buf) {
-            Ok(Event::Start(ref e)) if e.name().as_ref() == b"body" => {
-                writer.write_event(Event::Start(e.clone()))?;
-                in_body = true;
+impl<W: Write> BodyWriter<'_, W> {
+    fn whitespace(&mut self, t: &BytesText) {
+        let t = with_line_ending(t, &self.line_ending);
+        self.pending = Some(match self.pending.take() {
+            Some(pending) => BytesText::from_escaped(format!("{}{}", String::from_utf8_lossy(&pending), String::from_utf8_lossy(&t))),
+            None => t,
+        });
+    }
+
+    fn open(&mut self) -> Result<()> {
+        if let Some(start) = self.unopened.take() {
+            self.writer.write_event(Event::Start(start))?;
+        }
+        if let Some(pending) = self.pending.take() {
+            self.writer.write_event(Event::Text(pending))?;
+        }
+        Ok(())
+    }
 
-                break;
+    fn item(&mut self, item: &TmxItem) -> Result<()> {
+        match item {
+            TmxItem::Event(Event::Text(t)) if is_xml_whitespace(t) => self.whitespace(t),
+            TmxItem::Event(ev) => {
+                self.open()?;
+                self.writer.write_event(ev.borrow())?;
+            }
+            TmxItem::Tu(tu) => {
+                self.open()?;
+                write_tu(self.writer, tu, self.options, &self.line_ending)?;
             }
-            Ok(Event::Empty(ref e)) if e.name().as_ref() == b"body" => {
-                if files.len() > 1 {
-                    writer.write_event(Event::Text(BytesText::from_escaped("<body>")))?;
+        }
+        Ok(())
+    }
 
-                    break;
-                }
+    // copies the content of a <body> up to </body>; the whitespace in front of </body> is returned instead
+    fn copy<R: BufRead>(&mut self, reader: &mut TmxReader<R>, file: &str) -> Result<Option<BytesText<'static>>> {
+        loop {
+            match reader.read_item()? {
+                Some(TmxItem::Event(Event::End(e))) if e.name().as_ref() == b"body" => return Ok(self.pending.take()),
+                Some(item) => self.item(&item)?,
+                None => return Err(anyhow::anyhow!("Malformed TMX: <body> not closed in {}", file)),
+            }
+        }
+    }
+}
 
-                writer.write_event(Event::Empty(e.clone()))?;
-                in_body = false;
+// everything up to <body> is read; None when the file has no <header>
+fn read_header(file: &str) -> Result<Option<Header>> {
+    let mut reader = TmxReader::new(open_input(file).context(format!("Failed to open input file {}", file))?);
+    let mut indent = None;
 
-                break;
+    loop {
+        match reader.read_item()? {
+            Some(TmxItem::Event(ev @ (Event::Start(_) | Event::Empty(_)))) if is_header(&ev) => {
+                let mut header = Header::read(&mut reader, ev)?;
+                if let Some(indent) = indent {
+                    header.indent = indent;
+                }
+                return Ok(Some(header));
             }
-            Ok(Event::Eof) => return Err(anyhow::anyhow!("Malformed TMX: <body> not found")),
-            Ok(ev) => writer.write_event(ev.clone())?,
-            Err(e) => return Err(anyhow::anyhow!("XML parse error: {}", e)),
+            Some(TmxItem::Event(Event::Start(e) | Event::Empty(e))) if e.name().as_ref() == b"body" => return Ok(None),
+            Some(TmxItem::Event(Event::Text(t))) => indent = Some(String::from_utf8_lossy(&t).into_owned()),
+            Some(_) => indent = None,
+            None => return Err(anyhow::anyhow!("Malformed TMX: <body> not found in {}", file)),
         }
-        buf.clear();
     }
-    buf.clear();
+}
 
-    // Write all <tu> nodes from first file
-    if in_body {
-        loop {
-            match reader.read_event_into(&mut buf) {
-                Ok(Event::Empty(ref e)) if e.name().as_ref() == b"body" => {
-                    // empty <body/> tag
-                    break;
+fn is_header(ev: &Event) -> bool {
+    matches!(ev, Event::Start(e) | Event::Empty(e) if e.name().as_ref() == b"header")
+}
+
+/// Merges the headers of all files into the first one.
+///
+/// Props and notes the first header lacks are added. Different `srclang`s fail unless `mixed_srclang` is set,
+/// different `adminlang`s only print a warning.
+pub fn merge_headers(files: &[String], options: &ConcatOptions) -> Result<Option<Header>> {
+    let mut merged: Option<(&str, Header)> = None;
+
+    for file in files {
+        let Some(header) = read_header(file)? else {
+            continue;
+        };
+        let Some((first_file, merged)) = merged.as_mut() else {
+            merged = Some((file, header));
+            continue;
+        };
+
+        match (merged.attribute("srclang"), header.attribute("srclang")) {
+            (Some(a), Some(b)) if !a.eq_ignore_ascii_case(b) => {
+                if a != "*all*" && b != "*all*" && !options.mixed_srclang {
+                    return Err(anyhow::anyhow!(
+                        "Incompatible srclang: {} in {}, {} in {}. Use --mixed-srclang to merge them with srclang=\"*all*\"",
+                        a, first_file, b, file
+                    ));
                 }
-                Ok(Event::Start(ref e)) if e.name().as_ref() == b"tu" => {
-                    writer.write_event(Event::Start(e.clone()))?;
+                if a != "*all*" && b != "*all*" {
+                    eprintln!("Warning: {}: srclang {} differs from {} in {}, using *all*", file, b, a, first_file);
                 }
-                Ok(Event::Empty(ref e)) if e.name().as_ref() == b"tu" => {
-                    writer.write_event(Event::Empty(e.clone()))?;
+                merged.set_attribute("srclang", "*all*");
+            }
+            _ => {}
+        }
+
+        if let (Some(a), Some(b)) = (merged.attribute("adminlang"), header.attribute("adminlang"))
+            && !a.eq_ignore_ascii_case(b)
+        {
+            eprintln!("Warning: {}: adminlang {} differs from {} in {}", file, b, a, first_file);
+        }
+
+        for prop in header.props {
+            if !merged.props.contains(&prop) {
+                merged.add_prop(prop);
+            }
+        }
+        for note in header.notes {
+            if !merged.notes.contains(&note) {
+                merged.add_note(note);
+            }
+        }
+    }
+
+    let Some((_, mut merged)) = merged else {
+        return Ok(None);
+    };
+
+    if let Some(date) = &options.regenerate_header {
+        merged.set_attribute("creationtool", "tmx-utils");
+        merged.set_attribute("creationtoolversion", env!("CARGO_PKG_VERSION"));
+        merged.set_attribute("creationdate", &format_date(date));
+    }
+
+    Ok(Some(merged))
+}
+
+/// Concatenate TMX files by merging all <tu> nodes from subsequent files into the first one's <body>.
+///
+/// Everything around the TUs comes from the first file, including what follows its `</body>`. Indentation copied from
+/// the other files uses the first file's line endings.
+pub fn concat(files: &[String], output: &str, options: &ConcatOptions) -> Result<()> {
+    if files.is_empty() {
+        return Err(anyhow::anyhow!("No input files provided"));
+    }
+
+    // every input is opened twice, for the header and for the body, so stdin is kept in a temporary file
+    let mut stdin = None;
+    let mut paths = Vec::with_capacity(files.len());
+    for file in files {
+        if file != STDIO {
+            paths.push(file.clone());
+        } else if stdin.is_none() {
+            let temp = stdin_to_temp()?;
+            paths.push(temp.path().to_string_lossy().to_string());
+            stdin = Some(temp);
+        } else {
+            return Err(anyhow::anyhow!("stdin (-) can only be given once"));
+        }
+    }
+    // the quarantine names the inputs as given
+    let names = files;
+    let files = &paths;
+
+    let mut header = merge_headers(files, options)?;
+
+    // Open first file and parse header, <body>, and <tu> nodes
+    let mut reader = TmxReader::new(open_input(&files[0]).context(format!("Failed to open first input file {}", &files[0]))?)
+        .with_quarantine(options.quarantine.as_ref(), &names[0]);
+
+    let mut writer = TmxWriter::new(create_output(output).context(format!("Failed to create output file {}", output))?);
+
+    let mut line_ending = None;
+    // whitespace in front of <body>, the indentation of </body> when an empty <body/> is opened
+    let mut indent: Option<BytesText> = None;
+
+    // Write header and <body> start from first file
+    let body = loop {
+        match reader.read_item()? {
+            Some(TmxItem::Event(ev)) if is_body(&ev) => {
+                // the first file has no <header>: the merged one goes in front of <body>, indented like it
+                if let Some(header) = header.take() {
+                    for ev in header.events {
+                        writer.write_event(ev)?;
+                    }
+                    if let Some(t) = &indent {
+                        writer.write_event(Event::Text(t.clone()))?;
+                    }
                 }
-                Ok(Event::End(ref e)) if e.name().as_ref() == b"tu" => {
-                    writer.write_event(Event::End(e.clone()))?;
+                break ev;
+            }
+            // the merged header replaces the first file's one
+            Some(TmxItem::Event(ev)) if is_header(&ev) => {
+                Header::read(&mut reader, ev)?;
+                for ev in header.take().map(|header| header.events).unwrap_or_default() {
+                    writer.write_event(ev)?;
                 }
-                Ok(Event::End(ref e)) if e.name().as_ref() == b"body" => {
-                    // Don't write </body> yet
-                    break;
+                indent = None;
+            }
+            Some(TmxItem::Event(Event::Text(t))) => {
+                if line_ending.is_none() && t.contains(&b'\n') {
+                    line_ending = Some(if t.windows(2).any(|w| w == b"\r\n") { "\r\n" } else { "\n" });
                 }
-                Ok(Event::Eof) => return Err(anyhow::anyhow!("Malformed TMX: <body> not closed")),
-                Ok(Event::Empty(ref e)) if e.name().as_ref() == b"t5:n" => {
-                    write_t5_n_tag(&mut writer, e, unprotect)?
-                },
-                Ok(ev) => writer.write_event(ev.clone())?,
-                Err(e) => return Err(anyhow::anyhow!("XML parse error: {}", e)),
+                indent = Some(t.clone());
+                writer.write_event(Event::Text(t))?;
             }
-            buf.clear();
+            Some(item) => {
+                indent = None;
+                writer.write_item(&item)?;
+            }
+            None => return Err(anyhow::anyhow!("Malformed TMX: <body> not found in {}", files[0])),
         }
-    }
-    buf.clear();
+    };
+
+    let line_ending = line_ending.unwrap_or("\n");
+    let mut body_writer = BodyWriter {
+        writer: &mut writer,
+        options,
+        line_ending: line_ending.to_string(),
+        unopened: None,
+        pending: None,
+    };
+
+    // the whitespace in front of the first file's </body>, and </body> itself
+    let (closing, end) = match body {
+        Event::Start(e) => {
+            body_writer.writer.write_event(Event::Start(e.borrow()))?;
+            let closing = body_writer.copy(&mut reader, &files[0])?;
+            (closing, BytesEnd::new(String::from_utf8_lossy(e.name().as_ref()).into_owned()))
+        }
+        Event::Empty(e) => {
+            body_writer.unopened = Some(e.clone());
+            let closing = indent.map(|t| with_line_ending(&t, line_ending));
+            (closing, BytesEnd::new(String::from_utf8_lossy(e.name().as_ref()).into_owned()))
+        }
+        _ => return Err(anyhow::anyhow!("Malformed TMX: <body> not found in {}", files[0])),
+    };
 
     // For each subsequent file, extract <tu> nodes and write them into output
-    for file in &files[1..] {
-        let input_file = File::open(file).context(format!("Failed to open input file {}", file))?;
-        let mut reader = Reader::from_reader(BufReader::new(input_file));
-        reader.config_mut().trim_text(false);
-        let mut buf = Vec::new();
-        let mut just_skipped_node = false;
+    for (file, name) in files[1..].iter().zip(&names[1..]) {
+        let mut reader = TmxReader::new(open_input(file).context(format!("Failed to open input file {}", file))?)
+            .with_quarantine(options.quarantine.as_ref(), name);
+
+        // skip everything up to <body>; an empty <body/> has nothing to copy
         loop {
-            match reader.read_event_into(&mut buf) {
-                Ok(Event::Empty(ref e)) if e.name().as_ref() == b"body" => {
-                    // empty <body/> tag
+            match reader.read_item()? {
+                Some(TmxItem::Event(Event::Start(e))) if e.name().as_ref() == b"body" => {
+                    body_writer.copy(&mut reader, file)?;
                     break;
                 }
-                Ok(Event::Start(ref e)) if e.name().as_ref() == b"tu" => {
-                    writer.write_event(Event::Start(e.clone()))?;
-                }
-                Ok(Event::Empty(ref e)) if e.name().as_ref() == b"tu" => {
-                    writer.write_event(Event::Empty(e.clone()))?;
-                }
-                Ok(Event::End(ref e)) if e.name().as_ref() == b"tu" => {
-                    writer.write_event(Event::End(e.clone()))?;
-                }
-                Ok(Event::Empty(ref e)) if e.name().as_ref() == b"t5:n" => {
-                    write_t5_n_tag(&mut writer, e, unprotect)?
-                },
-                Ok(Event::Decl(_)) => { just_skipped_node = true; }
-                Ok(Event::Start(ref e)) if e.name().as_ref() == b"tmx" => { just_skipped_node = true; }
-                Ok(Event::Empty(ref e)) if e.name().as_ref() == b"tmx" => { just_skipped_node = true; }
-                Ok(Event::End(ref e)) if e.name().as_ref() == b"tmx" => { just_skipped_node = true; }
-                Ok(Event::Empty(ref e)) if e.name().as_ref() == b"header" => { just_skipped_node = true; }
-                Ok(Event::Start(ref e)) if e.name().as_ref() == b"body" => { just_skipped_node = true; }
-                Ok(Event::End(ref e)) if e.name().as_ref() == b"body" => {
-                    // Don't write </body> yet
-                    break;
-                }
-                Ok(Event::Text(ref t)) => {
-                    if just_skipped_node && is_xml_whitespace(t.as_ref()) {
-                        // skip writing this whitespace
-                        just_skipped_node = false;
-
-                        continue;
-                    }
-                    writer.write_event(Event::Text(t.clone()))?;
-                }
-                Ok(Event::Eof) => return Err(anyhow::anyhow!("Malformed TMX: <body> not closed")),
-                Ok(ev) => {
-                    writer.write_event(ev.clone())?
-                },
-                Err(e) => return Err(anyhow::anyhow!("XML parse error: {}", e)),
+                Some(TmxItem::Event(Event::Empty(e))) if e.name().as_ref() == b"body" => break,
+                None => return Err(anyhow::anyhow!("Malformed TMX: <body> not found in {}", file)),
+                Some(_) => {}
             }
-            buf.clear();
         }
     }
 
     // Write </body> and the rest of the first file (footer)
-    writer.write_event(Event::Text(BytesText::from_escaped("</body>\r</tmx>")))?;
+    match body_writer.unopened.take() {
+        Some(empty) => writer.write_event(Event::Empty(empty))?,
+        None => {
+            if let Some(closing) = closing {
+                writer.write_event(Event::Text(closing))?;
+            }
+            writer.write_event(Event::End(end))?;
+        }
+    }
 
-    Ok(())
-}
\ No newline at end of file
+    while let Some(item) = reader.read_item()? {
+        match item {
+            TmxItem::Event(Event::Text(t)) => writer.write_event(Event::Text(with_line_ending(&t, line_ending)))?,
+            item => writer.write_item(&item)?,
+        }
+    }
+
+    writer.into_inner().finish()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pretty_assertions::assert_eq;
+
+    fn files(names: &[&str]) -> Vec<String> {
+        names.iter().map(|name| format!("test-data/concat/{}.tmx", name)).collect()
+    }
+
+    fn assert_concat(names: &[&str], options: &ConcatOptions, expected: &str) {
+        let output = format!("test-data/concat/output_{}.tmx", expected);
+        concat(&files(names), &output, options).unwrap();
+
+        let expected = std::fs::read(format!("test-data/concat/{}.tmx", expected)).unwrap();
+        let result = std::fs::read(&output).unwrap();
+        std::fs::remove_file(&output).unwrap();
+
+        assert_eq!(String::from_utf8(expected).unwrap(), String::from_utf8(result).unwrap());
+    }
+
+    #[test]
+    fn test_concat_keeps_footer() {
+        assert_concat(&["lf_1", "lf_2"], &ConcatOptions::default(), "expected_lf");
+    }
+
+    #[test]
+    fn test_concat_crlf() {
+        assert_concat(&["crlf_1", "lf_2"], &ConcatOptions::default(), "expected_crlf");
+    }
+
+    #[test]
+    fn test_concat_empty_bodies() {
+        assert_concat(&["empty_body", "lf_1", "empty_body", "lf_2"], &ConcatOptions::default(), "expected_empty_first");
+        assert_concat(&["empty_body", "empty_body"], &ConcatOptions::default(), "expected_all_empty");
+    }
+
+    #[test]
+    fn test_concat_unprotect() {
+        assert_concat(&["lf_1", "lf_2"], &ConcatOptions { unprotect: true, ..Default::default() }, "expected_unprotect");
+    }
+
+    #[test]
+    fn test_concat_merges_headers() {
+        let options = ConcatOptions {
+            regenerate_header: Some(DateTime::from_timestamp(1_700_000_000, 0).unwrap()),
+            ..Default::default()
+        };
+        concat(&files(&["header_a", "header_b"]), "test-data/concat/output_header.tmx", &options).unwrap();
+        let output = std::fs::read_to_string("test-data/concat/output_header.tmx").unwrap();
+        std::fs::remove_file("test-data/concat/output_header.tmx").unwrap();
+
+        let header = format!(
+            "    <header creationtool=\"tmx-utils\" creationtoolversion=\"{}\" segtype=\"sentence\" adminlang=\"en-us\" srclang=\"de-DE\" o-tmf=\"t5memory\" datatype=\"plaintext\" creationdate=\"20231114T221320Z\">
+        <prop type=\"tmgr:description\">export a</prop>
+        <prop type=\"tmgr:description\">export b</prop>
+        <note>exported from 0.7</note>
+    </header>",
+            env!("CARGO_PKG_VERSION")
+        );
+        assert!(output.contains(&header), "{}", output);
+    }
+
+    #[test]
+    fn test_concat_header_from_later_file() {
+        assert_concat(&["no_header", "header_b"], &ConcatOptions::default(), "expected_no_header");
+    }
+
+    #[test]
+    fn test_concat_srclang_mismatch() {
+        let result = concat(&files(&["header_a", "header_c"]), "test-data/concat/output_mismatch.tmx", &ConcatOptions::default());
+        assert!(result.unwrap_err().to_string().contains("Incompatible srclang"));
+
+        let options = ConcatOptions { mixed_srclang: true, ..Default::default() };
+        let header = merge_headers(&files(&["header_c", "header_a"]), &options).unwrap().unwrap();
+        assert_eq!(Some("*all*"), header.attribute("srclang"));
+        assert_eq!(1, header.props.len());
+    }
+}
@@ -1,25 +1,201 @@
 use anyhow::{Context, Result};
+use globset::{Glob, GlobSet, GlobSetBuilder};
+use std::cmp::Ordering;
+use std::path::Path;
+use std::str::FromStr;
+use std::time::SystemTime;
 
-use crate::concat::concat;
+use crate::concat::{concat, ConcatOptions};
 
-pub fn concat_dir(input_dir: &String, output: &String, unprotect: bool) -> Result<()> {
-    let mut input_files: Vec<String> = Vec::new();
+/// Order of the files in the merged output.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum SortBy {
+    /// Natural order of the paths, so `part9.tmx` comes before `part10.tmx`.
+    #[default]
+    Name,
+    /// Oldest modification time first, ties in natural order.
+    Modified,
+}
 
-    for entry in std::fs::read_dir(input_dir).context("Cannot read input directory")? {
-        let entry = entry.context("Cannot read directory entry")?;
-        let path = entry.path();
-        if path.is_file() {
-            if let Some(ext) = path.extension() {
-                if ext == "tmx" {
-                    input_files.push(path.to_string_lossy().to_string());
+impl FromStr for SortBy {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "name" => Ok(SortBy::Name),
+            "modified" => Ok(SortBy::Modified),
+            _ => Err(anyhow::anyhow!("Unknown sort order '{}'. Expected name or modified", s)),
+        }
+    }
+}
+
+/// Which files of a directory are merged, and in which order.
+#[derive(Clone, Debug, Default)]
+pub struct DirOptions {
+    /// Descend into subdirectories.
+    pub recursive: bool,
+    /// Glob patterns; when given, a file must match one of them.
+    pub include: Vec<String>,
+    /// Glob patterns of files to leave out.
+    pub exclude: Vec<String>,
+    pub sort: SortBy,
+}
+
+/// The `.tmx` files (any case, possibly compressed as `.tmx.gz`, `.tmx.zst` or `.tmx.xz`) below `input_dir` selected by `options`, in merge order.
+///
+/// Patterns are matched against the path relative to `input_dir`, with `/` separators, and against the file name.
+pub fn list_files(input_dir: &str, options: &DirOptions) -> Result<Vec<String>> {
+    let include = glob_set(&options.include)?;
+    let exclude = glob_set(&options.exclude)?;
+
+    let mut files: Vec<(String, SystemTime, String)> = Vec::new();
+    let mut dirs = vec![Path::new(input_dir).to_path_buf()];
+
+    while let Some(dir) = dirs.pop() {
+        let entries = std::fs::read_dir(&dir).context(format!("Cannot read directory {}", dir.display()))?;
+        for entry in entries {
+            let entry = entry.context("Cannot read directory entry")?;
+            let path = entry.path();
+
+            // symlinked directories are not followed, so there are no cycles
+            if entry.file_type()?.is_dir() {
+                if options.recursive {
+                    dirs.push(path);
+                }
+                continue;
+            }
+            if !path.is_file() || !is_tmx(&path) {
+                continue;
+            }
+
+            let relative = path.strip_prefix(input_dir).unwrap_or(&path);
+            let relative = relative.components()
+                .map(|c| c.as_os_str().to_string_lossy())
+                .collect::<Vec<_>>()
+                .join("/");
+            let name = entry.file_name().to_string_lossy().to_string();
+
+            let matches = |set: &GlobSet| set.is_match(&relative) || set.is_match(&name);
+            if (!options.include.is_empty() && !matches(&include)) || matches(&exclude) {
+                continue;
+            }
+
+            let modified = match options.sort {
+                SortBy::Modified => entry.metadata()?.modified().context(format!("Cannot read modification time of {}", path.display()))?,
+                SortBy::Name => SystemTime::UNIX_EPOCH,
+            };
+            files.push((relative, modified, path.to_string_lossy().to_string()));
+        }
+    }
+
+    files.sort_by(|(a, a_modified, _), (b, b_modified, _)| a_modified.cmp(b_modified).then_with(|| natural_cmp(a, b)));
+
+    Ok(files.into_iter().map(|(_, _, path)| path).collect())
+}
+
+fn is_tmx(path: &Path) -> bool {
+    let name = path.to_string_lossy().to_ascii_lowercase();
+    [".tmx", ".tmx.gz", ".tmx.zst", ".tmx.xz"].iter().any(|ext| name.ends_with(ext))
+}
+
+fn glob_set(patterns: &[String]) -> Result<GlobSet> {
+    let mut builder = GlobSetBuilder::new();
+    for pattern in patterns {
+        builder.add(Glob::new(pattern).context(format!("Invalid glob pattern '{}'", pattern))?);
+    }
+    Ok(builder.build()?)
+}
+
+/// Compares runs of digits by their value and everything else case-insensitively, e.g. `part9` < `Part10`.
+/// Names that only differ in case or leading zeros are ordered by their bytes.
+fn natural_cmp(a: &str, b: &str) -> Ordering {
+    let (mut a_chars, mut b_chars) = (a.chars().peekable(), b.chars().peekable());
+
+    loop {
+        match (a_chars.peek().copied(), b_chars.peek().copied()) {
+            (None, None) => return a.cmp(b),
+            (None, Some(_)) => return Ordering::Less,
+            (Some(_), None) => return Ordering::Greater,
+            (Some(x), Some(y)) if x.is_ascii_digit() && y.is_ascii_digit() => {
+                let take_number = |chars: &mut std::iter::Peekable<std::str::Chars>| {
+                    let mut digits = String::new();
+                    while let Some(c) = chars.next_if(char::is_ascii_digit) {
+                        digits.push(c);
+                    }
+                    digits.trim_start_matches('0').to_string()
+                };
+                let (x, y) = (take_number(&mut a_chars), take_number(&mut b_chars));
+                let ordering = x.len().cmp(&y.len()).then_with(|| x.cmp(&y));
+                if ordering != Ordering::Equal {
+                    return ordering;
+                }
+            }
+            (Some(x), Some(y)) => {
+                let ordering = x.to_lowercase().cmp(y.to_lowercase());
+                if ordering != Ordering::Equal {
+                    return ordering;
                 }
+                a_chars.next();
+                b_chars.next();
             }
         }
     }
+}
+
+pub fn concat_dir(input_dir: &str, output: &str, dir_options: &DirOptions, options: &ConcatOptions) -> Result<()> {
+    let input_files = list_files(input_dir, dir_options)?;
 
     if input_files.is_empty() {
         return Err(anyhow::anyhow!("No .tmx files found in the input directory"));
     }
 
-    concat(&input_files, output, unprotect)
-}
\ No newline at end of file
+    concat(&input_files, output, options)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pretty_assertions::assert_eq;
+
+    #[test]
+    fn test_natural_cmp() {
+        let mut names = vec!["part10.tmx", "Part9.tmx", "part1.tmx", "part01.tmx", "part9b.tmx", "a/part2.tmx", "part.tmx"];
+        names.sort_by(|a, b| natural_cmp(a, b));
+        assert_eq!(vec!["a/part2.tmx", "part.tmx", "part01.tmx", "part1.tmx", "Part9.tmx", "part9b.tmx", "part10.tmx"], names);
+    }
+
+    #[test]
+    fn test_list_files() {
+        let dir = tempfile::tempdir().unwrap();
+        std::fs::create_dir(dir.path().join("sub")).unwrap();
+        for name in ["part10.TMX", "part9.tmx.gz", "part1.tmx", "notes.txt", "notes.gz", "draft_part2.tmx", "sub/part3.tmx"] {
+            std::fs::write(dir.path().join(name), "").unwrap();
+        }
+        let input_dir = dir.path().to_str().unwrap();
+        let list = |options: &DirOptions| -> Vec<String> {
+            list_files(input_dir, options).unwrap().iter()
+                .map(|path| Path::new(path).strip_prefix(input_dir).unwrap().to_string_lossy().replace('\\', "/"))
+                .collect()
+        };
+
+        assert_eq!(vec!["draft_part2.tmx", "part1.tmx", "part9.tmx.gz", "part10.TMX"], list(&DirOptions::default()));
+        assert_eq!(
+            vec!["part1.tmx", "part9.tmx.gz", "part10.TMX", "sub/part3.tmx"],
+            list(&DirOptions { recursive: true, exclude: vec!["draft_*".to_string()], ..Default::default() })
+        );
+        assert_eq!(
+            vec!["sub/part3.tmx"],
+            list(&DirOptions { recursive: true, include: vec!["sub/*".to_string()], ..Default::default() })
+        );
+
+        let now = SystemTime::now();
+        for (name, age) in [("part1.tmx", 10), ("part9.tmx.gz", 30), ("part10.TMX", 20), ("draft_part2.tmx", 0)] {
+            let file = std::fs::File::options().write(true).open(dir.path().join(name)).unwrap();
+            file.set_modified(now - std::time::Duration::from_secs(age)).unwrap();
+        }
+        assert_eq!(
+            vec!["part9.tmx.gz", "part10.TMX", "part1.tmx", "draft_part2.tmx"],
+            list(&DirOptions { sort: SortBy::Modified, ..Default::default() })
+        );
+    }
+}
@@ -0,0 +1,123 @@
+use anyhow::Result;
+use chrono::{DateTime, NaiveDate, NaiveDateTime, Utc};
+
+use crate::element::Element;
+use crate::tu::Tu;
+
+/// The date attributes TMX defines on `<tu>` (and `<tuv>`).
+pub const DATE_ATTRIBUTES: [&str; 3] = ["creationdate", "changedate", "lastusagedate"];
+
+// tried in order; all are read as UTC
+const DATE_TIME_FORMATS: [&str; 6] = [
+    "%Y%m%dT%H%M%SZ",
+    "%Y%m%dT%H%M%S",
+    "%Y-%m-%dT%H:%M:%S%.fZ",
+    "%Y-%m-%dT%H:%M:%S%.f",
+    "%Y-%m-%d %H:%M:%S%.f",
+    "%Y%m%d%H%M%S",
+];
+
+const DATE_FORMATS: [&str; 2] = ["%Y%m%d", "%Y-%m-%d"];
+
+/// Parses a TMX date. Besides the standard `YYYYMMDDThhmmssZ` this accepts the form without `Z`,
+/// ISO 8601 with dashes and colons (with or without `Z`, fractions or an offset) and dates without a time.
+pub fn parse_date(value: &str) -> Result<DateTime<Utc>> {
+    let value = value.trim();
+
+    for format in DATE_TIME_FORMATS {
+        if let Ok(naive) = NaiveDateTime::parse_from_str(value, format) {
+            return Ok(naive.and_utc());
+        }
+    }
+
+    if let Ok(date_time) = DateTime::parse_from_rfc3339(value) {
+        return Ok(date_time.with_timezone(&Utc));
+    }
+
+    for format in DATE_FORMATS {
+        if let Ok(date) = NaiveDate::parse_from_str(value, format) {
+            return Ok(date.and_hms_opt(0, 0, 0).unwrap_or_default().and_utc());
+        }
+    }
+
+    Err(anyhow::anyhow!("unrecognised date '{}'", value))
+}
+
+/// Formats a date the way TMX requires, e.g. `20160323T152428Z`.
+pub fn format_date(date: &DateTime<Utc>) -> String {
+    date.format("%Y%m%dT%H%M%SZ").to_string()
+}
+
+/// The date in a `<tu>` attribute. A malformed date is reported as a warning naming the TU
+/// and treated as missing; it is reported once, however often it is read.
+pub fn tu_date(tu: &Tu, attribute: &str) -> Option<DateTime<Utc>> {
+    let value = tu.attribute(attribute)?;
+
+    match parse_date(value) {
+        Ok(date) => Some(date),
+        Err(e) => {
+            let mut reported = tu.reported_dates.borrow_mut();
+            if !reported.iter().any(|(name, old)| name == attribute && old == value) {
+                eprintln!("Warning: {}: ignoring {}: {}", tu_label(tu), attribute, e);
+                reported.push((attribute.to_string(), value.to_string()));
+            }
+            None
+        }
+    }
+}
+
+/// Seconds since the epoch of a `<tu>` date attribute, 0 when it is missing or malformed.
+pub fn tu_timestamp(tu: &Tu, attribute: &str) -> i64 {
+    tu_date(tu, attribute).map_or(0, |date| date.timestamp())
+}
+
+/// Names a TU in messages: by `tuid`, or by its byte offset when it has none.
+pub fn tu_label(tu: &Tu) -> String {
+    match tu.attribute("tuid") {
+        Some(tuid) => format!("TU {}", tuid),
+        None => format!("TU at byte {}", tu.span.start),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pretty_assertions::assert_eq;
+    use quick_xml::events::{BytesStart, Event};
+
+    #[test]
+    fn test_parse_date_variants() {
+        let expected = "20160323T152428Z";
+        for value in [
+            "20160323T152428Z",
+            "20160323T152428",
+            "2016-03-23T15:24:28Z",
+            "2016-03-23T15:24:28.000Z",
+            "2016-03-23T15:24:28",
+            "2016-03-23 15:24:28",
+            "2016-03-23T16:24:28+01:00",
+            " 20160323T152428Z ",
+        ] {
+            assert_eq!(expected, format_date(&parse_date(value).unwrap()), "{}", value);
+        }
+
+        assert_eq!("20160323T000000Z", format_date(&parse_date("20160323").unwrap()));
+        assert_eq!("20160323T000000Z", format_date(&parse_date("2016-03-23").unwrap()));
+
+        for value in ["", "2016", "20161323T152428Z", "yesterday", "20160323T15"] {
+            assert!(parse_date(value).is_err(), "{}", value);
+        }
+    }
+
+    #[test]
+    fn test_tu_date_reported_once() {
+        let mut tu = Tu::from_events(vec![Event::Empty(BytesStart::new("tu").with_attributes([("creationdate", "yesterday")]))]);
+        assert_eq!(None, tu_date(&tu, "creationdate"));
+        assert_eq!(0, tu_timestamp(&tu, "creationdate"));
+        assert_eq!(1, tu.reported_dates.borrow().len());
+
+        tu.set_attribute("creationdate", "tomorrow");
+        assert_eq!(None, tu_date(&tu, "creationdate"));
+        assert_eq!(2, tu.reported_dates.borrow().len());
+    }
+}
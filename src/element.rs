@@ -0,0 +1,92 @@
+use quick_xml::events::{BytesEnd, BytesStart, BytesText, Event};
+
+use crate::tu::{attribute, event_text, with_attributes, Prop};
+
+/// Editing shared by the elements kept as raw events next to their typed attributes, props and notes:
+/// [`crate::tu::Tu`] and [`crate::header::Header`]. Every change updates both.
+pub trait Element {
+    fn attributes(&self) -> &[(String, String)];
+    fn attributes_mut(&mut self) -> &mut Vec<(String, String)>;
+    fn props_mut(&mut self) -> &mut Vec<Prop>;
+    fn notes_mut(&mut self) -> &mut Vec<String>;
+    fn events_mut(&mut self) -> &mut Vec<Event<'static>>;
+
+    /// Inserts the events of a new `<prop>` or `<note>` where the element keeps them.
+    fn insert_child(&mut self, child: Vec<Event<'static>>);
+
+    /// Unescaped value of an attribute.
+    fn attribute(&self, name: &str) -> Option<&str> {
+        self.attributes().iter().find(|(key, _)| key == name).map(|(_, value)| value.as_str())
+    }
+
+    /// Sets an attribute, adding it at the end when missing.
+    fn set_attribute(&mut self, name: &str, value: &str) {
+        match self.attributes_mut().iter_mut().find(|(key, _)| key == name) {
+            Some((_, old)) => *old = value.to_string(),
+            None => self.attributes_mut().push((name.to_string(), value.to_string())),
+        }
+
+        let attributes = self.attributes().to_vec();
+        if let Some(Event::Start(e) | Event::Empty(e)) = self.events_mut().first_mut() {
+            *e = with_attributes(e, &attributes);
+        }
+    }
+
+    fn add_prop(&mut self, prop: Prop) {
+        let start = BytesStart::new("prop").with_attributes([("type", prop.prop_type.as_str())]);
+        self.insert_child(child_events(start, &prop.value));
+        self.props_mut().push(prop);
+    }
+
+    fn add_note(&mut self, note: String) {
+        self.insert_child(child_events(BytesStart::new("note"), &note));
+        self.notes_mut().push(note);
+    }
+}
+
+fn child_events(start: BytesStart<'static>, text: &str) -> Vec<Event<'static>> {
+    let end = BytesEnd::new(String::from_utf8_lossy(start.name().as_ref()).into_owned());
+    vec![Event::Start(start), Event::Text(BytesText::new(text).into_owned()), Event::End(end)]
+}
+
+/// Collects the `<prop>` and `<note>` children of an element while its events are walked.
+#[derive(Default)]
+pub(crate) struct Children {
+    pub props: Vec<Prop>,
+    pub notes: Vec<String>,
+    text: Option<String>,
+    prop_type: String,
+}
+
+impl Children {
+    /// Call for every start of a direct child.
+    pub fn start(&mut self, e: &BytesStart) {
+        match e.name().as_ref() {
+            b"prop" => {
+                self.prop_type = attribute(e, "type").unwrap_or_default();
+                self.text = Some(String::new());
+            }
+            b"note" => self.text = Some(String::new()),
+            _ => {}
+        }
+    }
+
+    /// Call for every end of a direct child.
+    pub fn end(&mut self, e: &BytesEnd) {
+        match e.name().as_ref() {
+            b"prop" => self.props.push(Prop {
+                prop_type: std::mem::take(&mut self.prop_type),
+                value: self.text.take().unwrap_or_default(),
+            }),
+            b"note" => self.notes.push(self.text.take().unwrap_or_default()),
+            _ => {}
+        }
+    }
+
+    /// Call for every other event.
+    pub fn text(&mut self, ev: &Event) {
+        if let (Some(text), Some(t)) = (self.text.as_mut(), event_text(ev)) {
+            text.push_str(&t);
+        }
+    }
+}
@@ -0,0 +1,148 @@
+use anyhow::{Context, Result};
+use std::cmp::Reverse;
+use std::collections::BinaryHeap;
+use std::fs::File;
+use std::io::{BufReader, BufWriter, Read, Seek, SeekFrom, Write};
+
+/// A fixed-size record that can be spilled to disk.
+pub(crate) trait Record: Ord + Sized {
+    const SIZE: usize;
+
+    fn encode(&self, out: &mut Vec<u8>);
+    fn decode(bytes: &[u8]) -> Self;
+}
+
+/// Sorts more records than fit in memory by spilling sorted runs to temp files and merging them.
+pub(crate) struct ExternalSorter<T: Record> {
+    capacity: usize,
+    buffer: Vec<T>,
+    runs: Vec<File>,
+}
+
+impl<T: Record> ExternalSorter<T> {
+    /// `memory_limit` is the number of bytes the in-memory buffer may use.
+    pub fn new(memory_limit: usize) -> Self {
+        ExternalSorter {
+            capacity: (memory_limit / T::SIZE).max(1),
+            buffer: Vec::new(),
+            runs: Vec::new(),
+        }
+    }
+
+    pub fn push(&mut self, record: T) -> Result<()> {
+        self.buffer.push(record);
+        if self.buffer.len() >= self.capacity {
+            self.spill()?;
+        }
+        Ok(())
+    }
+
+    fn spill(&mut self) -> Result<()> {
+        self.buffer.sort_unstable();
+
+        let mut run = tempfile::tempfile().context("Cannot create temporary file")?;
+        let mut writer = BufWriter::new(&mut run);
+        let mut bytes = Vec::with_capacity(T::SIZE);
+        for record in self.buffer.drain(..) {
+            bytes.clear();
+            record.encode(&mut bytes);
+            writer.write_all(&bytes)?;
+        }
+        writer.flush()?;
+        drop(writer);
+
+        run.seek(SeekFrom::Start(0))?;
+        self.runs.push(run);
+        Ok(())
+    }
+
+    /// Returns all records in ascending order.
+    pub fn into_sorted(mut self) -> Result<Sorted<T>> {
+        if self.runs.is_empty() {
+            self.buffer.sort_unstable();
+            return Ok(Sorted::Memory(self.buffer.into_iter()));
+        }
+
+        if !self.buffer.is_empty() {
+            self.spill()?;
+        }
+
+        let mut runs: Vec<BufReader<File>> = self.runs.into_iter().map(BufReader::new).collect();
+        let mut heap = BinaryHeap::new();
+        for (i, run) in runs.iter_mut().enumerate() {
+            if let Some(record) = read_record(run)? {
+                heap.push(Reverse((record, i)));
+            }
+        }
+
+        Ok(Sorted::Merge { runs, heap })
+    }
+}
+
+fn read_record<T: Record>(run: &mut BufReader<File>) -> Result<Option<T>> {
+    let mut bytes = vec![0u8; T::SIZE];
+    match run.read_exact(&mut bytes) {
+        Ok(()) => Ok(Some(T::decode(&bytes))),
+        Err(e) if e.kind() == std::io::ErrorKind::UnexpectedEof => Ok(None),
+        Err(e) => Err(e.into()),
+    }
+}
+
+pub(crate) enum Sorted<T: Record> {
+    Memory(std::vec::IntoIter<T>),
+    Merge {
+        runs: Vec<BufReader<File>>,
+        heap: BinaryHeap<Reverse<(T, usize)>>,
+    },
+}
+
+impl<T: Record> Iterator for Sorted<T> {
+    type Item = Result<T>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        match self {
+            Sorted::Memory(records) => records.next().map(Ok),
+            Sorted::Merge { runs, heap } => {
+                let Reverse((record, i)) = heap.pop()?;
+                match read_record(&mut runs[i]) {
+                    Ok(Some(next)) => heap.push(Reverse((next, i))),
+                    Ok(None) => {}
+                    Err(e) => return Some(Err(e)),
+                }
+                Some(Ok(record))
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[derive(PartialEq, Eq, PartialOrd, Ord, Debug)]
+    struct Num(u64);
+
+    impl Record for Num {
+        const SIZE: usize = 8;
+
+        fn encode(&self, out: &mut Vec<u8>) {
+            out.extend_from_slice(&self.0.to_le_bytes());
+        }
+
+        fn decode(bytes: &[u8]) -> Self {
+            Num(u64::from_le_bytes(bytes.try_into().unwrap()))
+        }
+    }
+
+    #[test]
+    fn test_external_sort_merges_runs() {
+        // room for 3 records per run
+        let mut sorter = ExternalSorter::new(24);
+        for n in [9, 3, 7, 1, 8, 2, 6, 4, 5, 0] {
+            sorter.push(Num(n)).unwrap();
+        }
+
+        let sorted: Vec<u64> = sorter.into_sorted().unwrap().map(|r| r.unwrap().0).collect();
+        assert_eq!((0..10).collect::<Vec<_>>(), sorted);
+    }
+}
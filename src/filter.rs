@@ -1,342 +1,588 @@
 use anyhow::{Context, Result};
 use quick_xml::events::{BytesText, Event};
-use quick_xml::reader::Reader;
-use quick_xml::Writer;
+use std::cmp::Reverse;
+use std::collections::hash_map;
+use std::collections::HashMap;
+use std::fmt;
 use std::fs::File;
-use std::hash::Hasher;
-use std::io::{BufReader, BufWriter};
-use chrono::{NaiveDateTime, DateTime, Utc};
-
-use crate::attribute::get_attribute_value;
-
-pub struct SkipOptions {
-    pub(crate) skip_author: bool,
-    pub(crate) skip_document: bool,
-    pub(crate) skip_context: bool,
-    pub(crate) keep_diff_targets: bool,
+use std::io::{BufRead, BufReader, Read, Seek, SeekFrom, Write};
+use std::str::FromStr;
+
+use crate::date::{tu_date, tu_timestamp};
+use crate::element::Element;
+use crate::external::{ExternalSorter, Record};
+use crate::io::{create_output, open_input, open_seekable};
+use crate::key::{source_text, KeySpec};
+use crate::namespace::Namespaces;
+use crate::quarantine::Quarantine;
+use crate::reader::{TmxItem, TmxReader};
+use crate::report::{DuplicateGroup, Report, ReportOptions, TuSummary};
+use crate::t5n::{convert, T5nMode};
+use crate::tu::{Prop, Tu};
+use crate::writer::TmxWriter;
+
+/// Which TU of a group of duplicates survives.
+///
+/// When two TUs rank the same, the one that comes first in the file wins.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub enum Strategy {
+    /// Most recent `creationdate`.
+    #[default]
+    KeepNewest,
+    /// Oldest `creationdate`.
+    KeepOldest,
+    /// Most recent `changedate`, `creationdate` when a TU was never changed.
+    NewestChanged,
+    /// Highest `usagecount`, then most recent `creationdate`.
+    MostUsed,
+    /// Most recent `lastusagedate`, then most recent `creationdate`.
+    LastUsed,
+    /// TUs created by this user (case-insensitive), then most recent `creationdate`.
+    PreferAuthor(String),
+    /// The first TU in the file.
+    KeepFirst,
+    /// The last TU in the file.
+    KeepLast,
 }
 
-#[derive(Clone)]
-enum TmxNode<'a> {
-    Start(quick_xml::events::BytesStart<'a>),
-    End(quick_xml::events::BytesEnd<'a>),
-    Event(quick_xml::events::Event<'a>),
-    Text(quick_xml::events::BytesText<'a>),
+impl Strategy {
+    /// Higher ranks win. `created` is the TU's `creationdate` timestamp and `position` its index in the file.
+    pub(crate) fn rank(&self, tu: &Tu, created: i64, position: u64) -> (i64, i64) {
+        match self {
+            Strategy::KeepNewest => (created, 0),
+            Strategy::KeepOldest => (-created, 0),
+            Strategy::NewestChanged => {
+                let changed = tu_date(tu, "changedate").map_or(created, |date| date.timestamp());
+                (changed, created)
+            }
+            Strategy::MostUsed => {
+                let usage = tu.attribute("usagecount").and_then(|count| count.trim().parse().ok()).unwrap_or(0);
+                (usage, created)
+            }
+            Strategy::LastUsed => (tu_timestamp(tu, "lastusagedate"), created),
+            Strategy::PreferAuthor(author) => {
+                let preferred = tu.attribute("creationid").is_some_and(|id| id.eq_ignore_ascii_case(author));
+                (preferred as i64, created)
+            }
+            Strategy::KeepFirst => (0, 0),
+            Strategy::KeepLast => (position as i64, 0),
+        }
+    }
 }
 
-struct Tu<'a> {
-    timestamp: i64,
-    tu_content: Vec<TmxNode<'a>>,
+impl FromStr for Strategy {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "newest" => Ok(Strategy::KeepNewest),
+            "oldest" => Ok(Strategy::KeepOldest),
+            "newest-changed" => Ok(Strategy::NewestChanged),
+            "most-used" => Ok(Strategy::MostUsed),
+            "last-used" => Ok(Strategy::LastUsed),
+            "first" => Ok(Strategy::KeepFirst),
+            "last" => Ok(Strategy::KeepLast),
+            _ => match s.strip_prefix("author:").filter(|author| !author.is_empty()) {
+                Some(author) => Ok(Strategy::PreferAuthor(author.to_string())),
+                None => Err(anyhow::anyhow!(
+                    "Unknown strategy '{}'. Expected newest, oldest, newest-changed, most-used, last-used, first, last or author:<id>",
+                    s
+                )),
+            },
+        }
+    }
 }
 
-pub fn filter(
-    input: &String,
-    output: &String,
-    skip_options: SkipOptions
-) -> Result<()> {
-    // Usage: tmx_trimmer <input.tmx> <output.tmx> <N>
-    let infile = File::open(input).context("Cannot open input file")?;
-    let mut reader = Reader::from_reader(BufReader::new(infile));
-    // Keep whitespace as-is globally; we’ll selectively drop only the ws after skipped <tu>.
-    reader.config_mut().trim_text(false);
-
-    let outfile = File::create(output).context("Cannot create output file")?;
-    let mut writer = Writer::new(BufWriter::new(outfile));
-
-    let mut inside_tu = false;
-    let mut inside_doc_prop= false;
-    let mut inside_context_prop= false;
-    let mut inside_source_tuv = false;
-    let mut inside_source_seg = false;
-    let mut inside_target_seg = false;
-
-    // tu map. key is hash of fields and value is TU node
-    let mut tu_map: std::collections::HashMap<u64, Tu> = std::collections::HashMap::new();
-
-    let mut author_value: Option<Vec<u8>> = None;
-    let mut document_value: Option<Vec<u8>> = None;
-    let mut context_value: Option<Vec<u8>> = None;
-    let mut timestamp_value: Option<Vec<u8>> = None;
-    let mut source_lang: Option<Vec<u8>> = None;
+impl fmt::Display for Strategy {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Strategy::KeepNewest => write!(f, "newest"),
+            Strategy::KeepOldest => write!(f, "oldest"),
+            Strategy::NewestChanged => write!(f, "newest-changed"),
+            Strategy::MostUsed => write!(f, "most-used"),
+            Strategy::LastUsed => write!(f, "last-used"),
+            Strategy::PreferAuthor(author) => write!(f, "author:{}", author),
+            Strategy::KeepFirst => write!(f, "first"),
+            Strategy::KeepLast => write!(f, "last"),
+        }
+    }
+}
 
-    let mut buf = Vec::new();
-    let mut tu_content: Vec<TmxNode<'_>> = Vec::new();
-    let mut source_content: Vec<TmxNode<'_>> = Vec::new();
-    let mut target_content: Vec<TmxNode<'_>> = Vec::new();
-
-    loop {
-        match reader.read_event_into(&mut buf) {
-            Ok(Event::Decl(ref e)) => writer.write_event(Event::Decl(e.clone()))?,
-            Ok(Event::Start(ref e)) if e.name().as_ref() == b"tmx" => {
-                writer.write_event(Event::Text(BytesText::from_escaped("\n")))?;
-                writer.write_event(Event::Start(e.clone()))?
-            },
-            Ok(Event::Empty(ref e)) if e.name().as_ref() == b"header" => {
-                writer.write_event(Event::Text(BytesText::from_escaped("\n")))?;
-                writer.write_event(Event::Empty(e.clone()))?
-            },
-            Ok(Event::Start(ref e)) if e.name().as_ref() == b"body" => {
-                writer.write_event(Event::Text(BytesText::from_escaped("\n")))?;
-                writer.write_event(Event::Start(e.clone()))?
-            },
+/// Order of the surviving TUs in the output. Ties keep the input order.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Order {
+    /// Input order of the surviving TU.
+    Input,
+    /// `creationdate` ascending.
+    #[default]
+    Timestamp,
+    /// `creationdate` descending.
+    TimestampDesc,
+    /// Source text, alphabetically.
+    Source,
+    /// Grouped by `tmgr:docname`, alphabetically.
+    Docname,
+}
 
-            Ok(Event::Eof) => break,
+impl FromStr for Order {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "input" => Ok(Order::Input),
+            "timestamp" => Ok(Order::Timestamp),
+            "timestamp-desc" => Ok(Order::TimestampDesc),
+            "source" => Ok(Order::Source),
+            "docname" => Ok(Order::Docname),
+            _ => Err(anyhow::anyhow!(
+                "Unknown order '{}'. Expected input, timestamp, timestamp-desc, source or docname",
+                s
+            )),
+        }
+    }
+}
 
-            Ok(Event::Start(ref e)) if e.name().as_ref() == b"tu" => {
-                inside_tu = true;
+#[derive(Default)]
+pub struct FilterOptions {
+    /// Fields that make two TUs duplicates of each other.
+    pub key: KeySpec,
+    /// Which duplicate survives.
+    pub strategy: Strategy,
+    /// Copy the props and notes of dropped duplicates into the survivor, unless it already has them.
+    pub merge_losers: bool,
+    /// Order of the surviving TUs.
+    pub order: Order,
+    /// Where to write the list of duplicate groups and conflicting targets.
+    pub report: Option<ReportOptions>,
+    /// Memory ceiling in bytes. When set, only hashes, timestamps and byte offsets are kept
+    /// (spilled to temporary files above the ceiling) and the surviving TUs are re-read from the input.
+    pub memory_limit: Option<usize>,
+    /// Convert the `<t5:n>` placeholders of the surviving TUs, like the `t5n` command.
+    pub t5n: Option<T5nMode>,
+    /// Which inline elements are placeholders for `t5n`.
+    pub namespaces: Namespaces,
+    /// Put malformed TUs there and go on instead of failing.
+    pub quarantine: Option<Quarantine>,
+}
 
-                tu_content.clear();
-                source_content.clear();
-                target_content.clear();
+impl FilterOptions {
+    /// Fails on option combinations [`filter`] does not support, before it opens any file.
+    pub fn check(&self) -> Result<()> {
+        if self.merge_losers && self.memory_limit.is_some() {
+            return Err(anyhow::anyhow!("Merging dropped duplicates is not supported with a memory limit"));
+        }
+        if self.report.is_some() && self.memory_limit.is_some() {
+            return Err(anyhow::anyhow!("A duplicate report is not supported with a memory limit"));
+        }
+        if matches!(self.order, Order::Source | Order::Docname) && self.memory_limit.is_some() {
+            return Err(anyhow::anyhow!("Ordering by source or docname is not supported with a memory limit"));
+        }
+        Ok(())
+    }
+}
 
-                document_value = None;
-                context_value = None;
+struct Entry {
+    rank: (i64, i64),
+    timestamp: i64,
+    position: u64,
+    tu: Tu,
+    // props and notes of the dropped duplicates
+    merged_props: Vec<Prop>,
+    merged_notes: Vec<String>,
+    // every TU of the group and which of them is kept, only collected for the report
+    members: Vec<TuSummary>,
+    kept: usize,
+}
 
-                tu_content.push(TmxNode::Start(e.clone().into_owned()));
+impl Entry {
+    fn absorb(&mut self, loser: Tu) {
+        self.merged_props.extend(loser.props);
+        self.merged_notes.extend(loser.notes);
+    }
 
-                author_value = get_attribute_value(&e, b"creationid");
-                timestamp_value = get_attribute_value(&e, b"creationdate");
+    fn into_tu(self) -> Tu {
+        let mut tu = self.tu;
+        for prop in self.merged_props {
+            if !tu.props.contains(&prop) {
+                tu.add_prop(prop);
             }
-
-            Ok(Event::Start(ref e)) if e.name().as_ref() == b"prop" => {
-                if !inside_tu {
-                    continue;
-                }
-
-                tu_content.push(TmxNode::Start(e.clone().into_owned()));
-
-                if let Some(type_attr) = get_attribute_value(&e, b"type") {
-                    if type_attr.as_ref() as &[u8] == b"tmgr:docname" {
-                        inside_doc_prop = true;
-                        inside_context_prop = false;
-                        continue;
-                    } else if type_attr.as_ref() as &[u8] == b"tmgr:context" {
-                        inside_doc_prop = false;
-                        inside_context_prop = true;
-                        continue;
-                    }
-                }
+        }
+        for note in self.merged_notes {
+            if !tu.notes.contains(&note) {
+                tu.add_note(note);
             }
+        }
+        tu
+    }
+}
 
-            Ok(Event::Start(ref e)) if e.name().as_ref() == b"tuv" => {
-                if !inside_tu {
-                    continue;
-                }
+/// Groups TUs by their key and keeps the one of each group that ranks highest, ties going to the first one.
+/// Self-closing `<tu/>` elements are dropped. Shared by [`filter`] and the `dedupe` stage of a pipeline.
+pub(crate) struct Duplicates {
+    key: KeySpec,
+    strategy: Strategy,
+    merge_losers: bool,
+    // collect a summary of every TU of a group, for the report
+    members: bool,
+    // the language of the first <tuv> in the file is the source language
+    source_lang: Option<String>,
+    position: u64,
+    groups: HashMap<u64, Entry>,
+    dropped: usize,
+}
 
-                tu_content.push(TmxNode::Start(e.clone().into_owned()));
+impl Duplicates {
+    pub(crate) fn new(key: KeySpec, strategy: Strategy) -> Self {
+        Duplicates {
+            key,
+            strategy,
+            merge_losers: false,
+            members: false,
+            source_lang: None,
+            position: 0,
+            groups: HashMap::new(),
+            dropped: 0,
+        }
+    }
 
-                if let Some(lang) = get_attribute_value(&e, b"xml:lang") {
-                    if source_lang.is_none() {
-                        source_lang = Some(lang.clone());
-                    }
+    pub(crate) fn add(&mut self, tu: Tu) {
+        if tu.is_empty_element() {
+            return;
+        }
+        if self.source_lang.is_none() {
+            self.source_lang = tu.tuvs.first().map(|tuv| tuv.lang.clone());
+        }
 
-                    inside_source_tuv = source_lang.as_ref().map_or(false, |sl| &lang == sl);
-                }
+        let hash = self.key.hash(&tu, self.source_lang.as_deref());
+        let timestamp = tu_timestamp(&tu, "creationdate");
+        let rank = self.strategy.rank(&tu, timestamp, self.position);
+        let position = self.position;
+        self.position += 1;
+
+        let summary = self.members.then(|| TuSummary::new(&tu, self.source_lang.as_deref()));
+
+        match self.groups.entry(hash) {
+            hash_map::Entry::Vacant(slot) => {
+                slot.insert(Entry {
+                    rank,
+                    timestamp,
+                    position,
+                    tu,
+                    merged_props: Vec::new(),
+                    merged_notes: Vec::new(),
+                    members: summary.into_iter().collect(),
+                    kept: 0,
+                });
             }
-
-            Ok(Event::Start(ref e)) if e.name().as_ref() == b"seg" => {
-                if !inside_tu {
-                    continue;
+            // if new TU ranks higher, replace existing TU
+            hash_map::Entry::Occupied(mut slot) => {
+                let entry = slot.get_mut();
+                entry.members.extend(summary);
+                self.dropped += 1;
+
+                let loser = if rank > entry.rank {
+                    entry.rank = rank;
+                    entry.timestamp = timestamp;
+                    entry.position = position;
+                    entry.kept = entry.members.len().saturating_sub(1);
+                    std::mem::replace(&mut entry.tu, tu)
+                } else {
+                    tu
+                };
+
+                if self.merge_losers {
+                    entry.absorb(loser);
                 }
+            }
+        }
+    }
 
-                tu_content.push(TmxNode::Start(e.clone().into_owned()));
+    /// Number of TUs dropped as duplicates so far.
+    pub(crate) fn dropped(&self) -> usize {
+        self.dropped
+    }
 
-                inside_source_seg = inside_source_tuv;
-                inside_target_seg = !inside_source_tuv;
-            }
+    // the winners in input order
+    fn take_entries(&mut self) -> Vec<Entry> {
+        let mut entries: Vec<Entry> = self.groups.drain().map(|(_, entry)| entry).collect();
+        entries.sort_by_key(|entry| entry.position);
+        entries
+    }
 
-            Ok(Event::End(ref e)) if e.name().as_ref() == b"seg" => {
-                tu_content.push(TmxNode::End(e.clone().into_owned()));
+    /// The winners in input order, with the props and notes of their duplicates when merging.
+    pub(crate) fn take_tus(&mut self) -> Vec<Tu> {
+        self.take_entries().into_iter().map(Entry::into_tu).collect()
+    }
+}
 
-                inside_source_seg = false;
-                inside_target_seg = false;
-            }
+pub fn filter(
+    input: &str,
+    output: &str,
+    options: &FilterOptions
+) -> Result<()> {
+    options.check()?;
 
-            Ok(Event::Empty(ref e)) if e.name().as_ref() == b"tu" => {}
+    let mut writer = TmxWriter::new(create_output(output).context("Cannot create output file")?);
 
-            Ok(Event::End(ref e)) if e.name().as_ref() == b"tu" => {
-                inside_tu = false;
+    match options.memory_limit {
+        Some(memory_limit) => {
+            let infile = open_seekable(input).context("Cannot open input file")?;
+            let reader = TmxReader::new(BufReader::new(infile.try_clone()?))
+                .with_quarantine(options.quarantine.as_ref(), input);
+            filter_external(reader, infile, &mut writer, options, memory_limit)?
+        }
+        None => {
+            let reader = TmxReader::new(open_input(input).context("Cannot open input file")?)
+                .with_quarantine(options.quarantine.as_ref(), input);
+            filter_in_memory(reader, &mut writer, options)?
+        }
+    }
 
-                let mut hasher = std::collections::hash_map::DefaultHasher::new();
+    writer.write_event(Event::Text(BytesText::from_escaped("\n</body>\n</tmx>")))?;
 
-                // hash source content and other fields unless skipped
+    writer.into_inner().finish()
+}
 
-                for node in &source_content {
-                    match node {
-                        TmxNode::Start(e) => std::hash::Hash::hash_slice(e.name().as_ref(), &mut hasher),
-                        TmxNode::End(e) => std::hash::Hash::hash_slice(e.name().as_ref(), &mut hasher),
-                        TmxNode::Event(ev) => std::hash::Hash::hash_slice(ev.as_ref(), &mut hasher),
-                        TmxNode::Text(t) => std::hash::Hash::hash_slice(t.as_ref(), &mut hasher),
-                    }
-                }
+fn filter_in_memory<R: BufRead, W: Write>(
+    reader: TmxReader<R>,
+    writer: &mut TmxWriter<W>,
+    options: &FilterOptions
+) -> Result<()> {
+    let mut duplicates = Duplicates::new(options.key.clone(), options.strategy.clone());
+    duplicates.merge_losers = options.merge_losers;
+    duplicates.members = options.report.is_some();
+
+    for item in reader {
+        match item? {
+            TmxItem::Event(ev) => write_prolog_event(writer, ev)?,
+            TmxItem::Tu(tu) => duplicates.add(tu),
+        }
+    }
 
-                if !skip_options.skip_author {
-                    if let Some(author) = &author_value {
-                        let upper = author.iter().map(|b| b.to_ascii_uppercase()).collect::<Vec<_>>();
-                        std::hash::Hash::hash_slice(&upper, &mut hasher);
-                    }
-                }
-                if !skip_options.skip_document {
-                    if let Some(document) = &document_value {
-                        std::hash::Hash::hash_slice(document, &mut hasher);
-                    }
-                }
-                if !skip_options.skip_context {
-                    // if no context, hash "-"
-                    match &context_value {
-                        Some(context) => {
-                            if context.is_empty() {
-                                std::hash::Hash::hash_slice(b"-", &mut hasher);
-                            } else {
-                                std::hash::Hash::hash_slice(context, &mut hasher);
-                            }
-                        },
-                        None => std::hash::Hash::hash_slice(b"-", &mut hasher),
-                    }
-                }
+    // write all TU nodes in the requested order, ties in input order
+    let mut tu_list = duplicates.take_entries();
+    let source_lang = duplicates.source_lang;
+    match options.order {
+        Order::Input => {}
+        Order::Timestamp => tu_list.sort_by_key(|entry| entry.timestamp),
+        Order::TimestampDesc => tu_list.sort_by_key(|entry| Reverse(entry.timestamp)),
+        Order::Source => tu_list.sort_by_cached_key(|entry| source_text(&entry.tu, source_lang.as_deref())),
+        Order::Docname => tu_list.sort_by_cached_key(|entry| entry.tu.prop("tmgr:docname").unwrap_or_default().to_string()),
+    }
 
-                if skip_options.keep_diff_targets {
-                    for node in &target_content {
-                        match node {
-                            TmxNode::Start(e) => std::hash::Hash::hash_slice(e.name().as_ref(), &mut hasher),
-                            TmxNode::End(e) => std::hash::Hash::hash_slice(e.name().as_ref(), &mut hasher),
-                            TmxNode::Event(ev) => std::hash::Hash::hash_slice(ev.as_ref(), &mut hasher),
-                            TmxNode::Text(t) => std::hash::Hash::hash_slice(t.as_ref(), &mut hasher),
-                        }
-                    }
-                }
+    if let Some(report_options) = &options.report {
+        let mut report = Report::default();
+        for entry in tu_list.iter_mut().filter(|entry| entry.members.len() > 1) {
+            let mut dropped = std::mem::take(&mut entry.members);
+            let kept = dropped.remove(entry.kept);
+            entry.members.push(kept.clone());
+
+            report.duplicates.push(DuplicateGroup {
+                key: options.key.values(&entry.tu, source_lang.as_deref()),
+                kept,
+                dropped,
+            });
+        }
+        report.find_conflicts(tu_list.iter().filter_map(|entry| entry.members.first().cloned()));
+        report.write(report_options)?;
+    }
 
-                let hash = hasher.finish();
+    for entry in tu_list {
+        // write newline before each tu
+        writer.write_event(Event::Text(BytesText::from_escaped("\n")))?;
+        let mut tu = entry.into_tu();
+        if let Some(mode) = options.t5n {
+            convert(&mut tu, mode, &options.namespaces);
+        }
+        writer.write_tu(&tu)?;
+    }
 
-                tu_content.push(TmxNode::End(e.clone().into_owned()));
+    Ok(())
+}
 
-                let mut tu_timestamp: i64 = 0;
+/// A TU in the first pass of the external filter: sorted by hash, the winner of each group first.
+#[derive(PartialEq, Eq)]
+struct IndexRecord {
+    hash: u64,
+    rank: (i64, i64),
+    timestamp: i64,
+    start: u64,
+    end: u64,
+}
 
-                // parse timestamp from string like 20160323T152428Z
-                if let Some(ts) = &timestamp_value {
-                    let ts_str = std::str::from_utf8(ts)
-                        .context("Failed to parse timestamp as UTF-8")?;
+impl Ord for IndexRecord {
+    fn cmp(&self, other: &Self) -> std::cmp::Ordering {
+        // highest rank first, the earlier one in the file on equal ranks
+        (self.hash, Reverse(self.rank), self.start).cmp(&(other.hash, Reverse(other.rank), other.start))
+    }
+}
 
-                    let naive = NaiveDateTime::parse_from_str(&ts_str[..15], "%Y%m%dT%H%M%S")?;
-                    let date_time = DateTime::<Utc>::from_naive_utc_and_offset(naive, Utc);
+impl PartialOrd for IndexRecord {
+    fn partial_cmp(&self, other: &Self) -> Option<std::cmp::Ordering> {
+        Some(self.cmp(other))
+    }
+}
 
-                    tu_timestamp = date_time.timestamp();
-                }
+impl Record for IndexRecord {
+    const SIZE: usize = 48;
 
-                if !tu_map.contains_key(&hash) {
-                    // write TU node
-                    let tu = Tu {
-                        timestamp: tu_timestamp,
-                        tu_content: tu_content.clone(),
-                    };
-                    tu_map.insert(hash, tu);
+    fn encode(&self, out: &mut Vec<u8>) {
+        out.extend_from_slice(&self.hash.to_le_bytes());
+        out.extend_from_slice(&self.rank.0.to_le_bytes());
+        out.extend_from_slice(&self.rank.1.to_le_bytes());
+        out.extend_from_slice(&self.timestamp.to_le_bytes());
+        out.extend_from_slice(&self.start.to_le_bytes());
+        out.extend_from_slice(&self.end.to_le_bytes());
+    }
 
-                    continue;
-                }
+    fn decode(bytes: &[u8]) -> Self {
+        let word = |i: usize| <[u8; 8]>::try_from(&bytes[i * 8..i * 8 + 8]).unwrap();
+        IndexRecord {
+            hash: u64::from_le_bytes(word(0)),
+            rank: (i64::from_le_bytes(word(1)), i64::from_le_bytes(word(2))),
+            timestamp: i64::from_le_bytes(word(3)),
+            start: u64::from_le_bytes(word(4)),
+            end: u64::from_le_bytes(word(5)),
+        }
+    }
+}
 
-                // if new TU has more recent timestamp, replace existing TU
-                if let Some(tu) = tu_map.get_mut(&hash) {
-                    if tu_timestamp > tu.timestamp {
-                        tu.timestamp = tu_timestamp;
-                        tu.tu_content = tu_content.clone();
-                    }
-                }   
-            }
+/// A surviving TU in the second pass of the external filter, in output order.
+#[derive(PartialEq, Eq, PartialOrd, Ord)]
+struct OutputRecord {
+    // timestamp, negated timestamp or 0 for input order
+    order: i64,
+    start: u64,
+    end: u64,
+}
 
-            Ok(Event::Text(ref t)) => {
-                if !inside_tu {
-                    continue;
-                }
+impl Record for OutputRecord {
+    const SIZE: usize = 24;
 
-                tu_content.push(TmxNode::Text(t.clone().into_owned()));
+    fn encode(&self, out: &mut Vec<u8>) {
+        out.extend_from_slice(&self.order.to_le_bytes());
+        out.extend_from_slice(&self.start.to_le_bytes());
+        out.extend_from_slice(&self.end.to_le_bytes());
+    }
 
-                if inside_source_seg {
-                    source_content.push(TmxNode::Text(t.clone().into_owned()));
-                }
+    fn decode(bytes: &[u8]) -> Self {
+        let word = |i: usize| <[u8; 8]>::try_from(&bytes[i * 8..i * 8 + 8]).unwrap();
+        OutputRecord {
+            order: i64::from_le_bytes(word(0)),
+            start: u64::from_le_bytes(word(1)),
+            end: u64::from_le_bytes(word(2)),
+        }
+    }
+}
 
-                if inside_target_seg {
-                    target_content.push(TmxNode::Text(t.clone().into_owned()));
-                }
+fn filter_external<W: Write>(
+    reader: TmxReader<BufReader<File>>,
+    mut infile: File,
+    writer: &mut TmxWriter<W>,
+    options: &FilterOptions,
+    memory_limit: usize,
+) -> Result<()> {
+    let mut index: ExternalSorter<IndexRecord> = ExternalSorter::new(memory_limit);
 
-                if inside_doc_prop {
-                    document_value = Some(t.as_ref().to_vec());
-                    inside_doc_prop = false;
-                    continue;
-                } else if inside_context_prop {
-                    context_value = Some(t.as_ref().to_vec());
-                    inside_context_prop = false;
-                    continue;
-                }
-            }
+    // the language of the first <tuv> in the file is the source language
+    let mut source_lang: Option<String> = None;
+    let mut position = 0u64;
 
-            Ok(ev) => {
-                if inside_tu {
-                    if inside_source_seg {
-                        source_content.push(TmxNode::Event(ev.clone().into_owned()));
-                    }
+    // first pass: hash every TU and remember where it is
+    for item in reader {
+        match item? {
+            TmxItem::Event(ev) => write_prolog_event(writer, ev)?,
 
-                    if inside_target_seg {
-                        target_content.push(TmxNode::Event(ev.clone().into_owned()));
-                    }
+            TmxItem::Tu(tu) if tu.is_empty_element() => {}
 
-                    // accumulate TU content
-                    tu_content.push(TmxNode::Event(ev.into_owned()));
+            TmxItem::Tu(tu) => {
+                if source_lang.is_none() {
+                    source_lang = tu.tuvs.first().map(|tuv| tuv.lang.clone());
                 }
 
-                continue;
+                let timestamp = tu_timestamp(&tu, "creationdate");
+                index.push(IndexRecord {
+                    hash: options.key.hash(&tu, source_lang.as_deref()),
+                    rank: options.strategy.rank(&tu, timestamp, position),
+                    timestamp,
+                    start: tu.span.start,
+                    end: tu.span.end,
+                })?;
+                position += 1;
             }
+        }
+    }
 
-            Err(e) => return Err(anyhow::anyhow!(
-                "XML parse error at {:?}: {}",
-                reader.error_position(),
-                e
-            )),
+    // keep the first record of each hash group, sorted in output order
+    let mut winners: ExternalSorter<OutputRecord> = ExternalSorter::new(memory_limit);
+    let mut last_hash = None;
+    for record in index.into_sorted()? {
+        let record = record?;
+        if last_hash == Some(record.hash) {
+            continue;
         }
-        buf.clear();
-    }
-
-    // write all TU nodes from map sorted by timestamp
-    let mut tu_list: Vec<&Tu> = tu_map.values().collect();
-    // sort by timestamp ascending
-    tu_list.sort_by_key(|tu| tu.timestamp);
-
-    for tu in tu_list {
-        for node in &tu.tu_content {
-            match node {
-                TmxNode::Start(e) => {
-                    // if start of tu tag -> write newline before
-                    if e.name().as_ref() == b"tu" {
-                        writer.write_event(Event::Text(BytesText::from_escaped("\n")))?;
-                    }
-                    writer.write_event(Event::Start(e.clone()))?
-                },
-                TmxNode::End(e) => writer.write_event(Event::End(e.clone()))?,
-                TmxNode::Event(ev) => writer.write_event(ev.clone())?,
-                TmxNode::Text(t) => writer.write_event(Event::Text(t.clone()))?,
+        last_hash = Some(record.hash);
+
+        let order = match options.order {
+            Order::Timestamp => record.timestamp,
+            Order::TimestampDesc => -record.timestamp,
+            _ => 0,
+        };
+        winners.push(OutputRecord { order, start: record.start, end: record.end })?;
+    }
+
+    // second pass: copy the winning TUs from the input
+    let mut buf = Vec::new();
+    for record in winners.into_sorted()? {
+        let record = record?;
+
+        buf.resize((record.end - record.start) as usize, 0);
+        infile.seek(SeekFrom::Start(record.start))?;
+        infile.read_exact(&mut buf).context("Cannot re-read TU from input file")?;
+
+        // write newline before each tu
+        writer.write_event(Event::Text(BytesText::from_escaped("\n")))?;
+        match options.t5n {
+            Some(mode) => {
+                let mut tu = TmxReader::new(buf.as_slice()).tus().next()
+                    .context("Cannot re-read TU from input file")??;
+                convert(&mut tu, mode, &options.namespaces);
+                writer.write_tu(&tu)?;
             }
+            None => writer.write_raw(&buf)?,
         }
     }
 
-    writer.write_event(Event::Text(BytesText::from_escaped("\n</body>\n</tmx>")))?;
-
     Ok(())
 }
 
+// only the declaration, <tmx>, <header/> and <body> are kept outside of TUs
+fn write_prolog_event<W: Write>(writer: &mut TmxWriter<W>, ev: Event<'static>) -> Result<()> {
+    match ev {
+        Event::Decl(_) => writer.write_event(ev),
+        Event::Start(ref e) if e.name().as_ref() == b"tmx" || e.name().as_ref() == b"body" => {
+            writer.write_event(Event::Text(BytesText::from_escaped("\n")))?;
+            writer.write_event(ev)
+        }
+        Event::Empty(ref e) if e.name().as_ref() == b"header" => {
+            writer.write_event(Event::Text(BytesText::from_escaped("\n")))?;
+            writer.write_event(ev)
+        }
+        _ => Ok(()),
+    }
+}
+
 // tests
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::report::ReportFormat;
     use pretty_assertions::{assert_eq};
 
     #[test]
     fn test_filter_no_skip_keep() {
-        let skip_options = SkipOptions {
-            skip_author: false,
-            skip_document: false,
-            skip_context: false,
-            keep_diff_targets: true,
-        };
-        let result = filter(&"test-data/filter/test.tmx".to_string(), &"test_no_skip_keep.tmx".to_string(), skip_options);
+        let key: KeySpec = "source,target,creationid,prop:tmgr:docname,prop:tmgr:context".parse().unwrap();
+        let result = filter("test-data/filter/test.tmx", "test_no_skip_keep.tmx", &FilterOptions { key, ..Default::default() });
         assert!(result.is_ok());
 
         let expected = std::fs::read_to_string("test-data/filter/test_no_skip_keep.tmx").unwrap();
@@ -350,13 +596,8 @@ mod tests {
 
     #[test]
     fn test_filter_no_skip_not_keep() {
-        let skip_options = SkipOptions {
-            skip_author: false,
-            skip_document: false,
-            skip_context: false,
-            keep_diff_targets: false,
-        };
-        let result = filter(&"test-data/filter/test.tmx".to_string(), &"output_no_skip_no_keep.tmx".to_string(), skip_options);
+        let key: KeySpec = "source,creationid,prop:tmgr:docname,prop:tmgr:context".parse().unwrap();
+        let result = filter("test-data/filter/test.tmx", "output_no_skip_no_keep.tmx", &FilterOptions { key, ..Default::default() });
         assert!(result.is_ok());
 
         let expected = std::fs::read_to_string("test-data/filter/test_no_skip_no_keep.tmx").unwrap();
@@ -368,51 +609,192 @@ mod tests {
         std::fs::remove_file("output_no_skip_no_keep.tmx").unwrap();
     }
 
+    #[test]
+    fn test_filter_external_no_skip_keep() {
+        let key: KeySpec = "source,target,creationid,prop:tmgr:docname,prop:tmgr:context".parse().unwrap();
+        // room for two index records, so the index is spilled to several runs
+        let options = FilterOptions { key, memory_limit: Some(2 * IndexRecord::SIZE), ..Default::default() };
+        let result = filter("test-data/filter/test.tmx", "output_external_no_skip_keep.tmx", &options);
+        assert!(result.is_ok());
+
+        let expected = std::fs::read_to_string("test-data/filter/test_no_skip_keep.tmx").unwrap();
+        let output = std::fs::read_to_string("output_external_no_skip_keep.tmx").unwrap();
+
+        // remove output file after test
+        std::fs::remove_file("output_external_no_skip_keep.tmx").unwrap();
+
+        assert_eq!(expected, output);
+    }
+
+    #[test]
+    fn test_filter_external_compressed() {
+        let dir = tempfile::tempdir().unwrap();
+        let input = dir.path().join("test.tmx.gz").to_string_lossy().to_string();
+        let output = dir.path().join("output.tmx.zst").to_string_lossy().to_string();
+
+        let mut writer = crate::io::create_output(&input).unwrap();
+        std::io::copy(&mut File::open("test-data/filter/test.tmx").unwrap(), &mut writer).unwrap();
+        writer.finish().unwrap();
+
+        let key: KeySpec = "source,target,creationid,prop:tmgr:docname,prop:tmgr:context".parse().unwrap();
+        let options = FilterOptions { key, memory_limit: Some(64), ..Default::default() };
+        filter(&input, &output, &options).unwrap();
+
+        let expected = std::fs::read_to_string("test-data/filter/test_no_skip_keep.tmx").unwrap();
+        let mut result = String::new();
+        open_input(&output).unwrap().read_to_string(&mut result).unwrap();
+
+        assert_eq!(expected, result);
+    }
+
     #[test]
     fn test_filter_skip_author() {
-        let skip_options = SkipOptions {
-            skip_author: true,
-            skip_document: false,
-            skip_context: false,
-            keep_diff_targets: false,
-        };
-        let result = filter(&"test.tmx".to_string(), &"output_skip_author.tmx".to_string(), skip_options);
+        let key: KeySpec = "source,prop:tmgr:docname,prop:tmgr:context".parse().unwrap();
+        let result = filter("test-data/filter/test.tmx", "output_skip_author.tmx", &FilterOptions { key, ..Default::default() });
         assert!(result.is_ok());
+
+        std::fs::remove_file("output_skip_author.tmx").unwrap();
     }
 
     #[test]
     fn test_filter_skip_document() {
-        let skip_options = SkipOptions {
-            skip_author: false,
-            skip_document: true,
-            skip_context: false,
-            keep_diff_targets: false,
-        };
-        let result = filter(&"test.tmx".to_string(), &"output_skip_document.tmx".to_string(), skip_options);
+        let key: KeySpec = "source,creationid,prop:tmgr:context".parse().unwrap();
+        let result = filter("test-data/filter/test.tmx", "output_skip_document.tmx", &FilterOptions { key, ..Default::default() });
         assert!(result.is_ok());
+
+        std::fs::remove_file("output_skip_document.tmx").unwrap();
     }
 
     #[test]
     fn test_filter_skip_context() {
-        let skip_options = SkipOptions {
-            skip_author: false,
-            skip_document: false,
-            skip_context: true,
-            keep_diff_targets: false,
-        };
-        let result = filter(&"test.tmx".to_string(), &"output_skip_context.tmx".to_string(), skip_options);
+        let key: KeySpec = "source,creationid,prop:tmgr:docname".parse().unwrap();
+        let result = filter("test-data/filter/test.tmx", "output_skip_context.tmx", &FilterOptions { key, ..Default::default() });
         assert!(result.is_ok());
+
+        std::fs::remove_file("output_skip_context.tmx").unwrap();
     }
 
     #[test]
     fn test_filter_keep_diff_targets() {
-        let skip_options = SkipOptions {
-            skip_author: false,
-            skip_document: false,
-            skip_context: false,
-            keep_diff_targets: true,
-        };
-        let result = filter(&"test.tmx".to_string(), &"output_keep_diff_targets.tmx".to_string(), skip_options);
+        let key: KeySpec = "source,target,creationid,prop:tmgr:docname,prop:tmgr:context".parse().unwrap();
+        let result = filter("test-data/filter/test.tmx", "output_keep_diff_targets.tmx", &FilterOptions { key, ..Default::default() });
         assert!(result.is_ok());
+
+        std::fs::remove_file("output_keep_diff_targets.tmx").unwrap();
     }
-}
\ No newline at end of file
+
+    fn filter_to_string(output: &str, options: &FilterOptions) -> String {
+        filter("test-data/filter/test.tmx", output, options).unwrap();
+        let result = std::fs::read_to_string(output).unwrap();
+        std::fs::remove_file(output).unwrap();
+        result
+    }
+
+    #[test]
+    fn test_filter_strategies() {
+        for (strategy, tuid) in [("newest", "3"), ("oldest", "1"), ("first", "1"), ("last", "4"), ("author:MANAGER1", "4")] {
+            let options = FilterOptions {
+                key: "source".parse().unwrap(),
+                strategy: strategy.parse().unwrap(),
+                ..Default::default()
+            };
+            let output = filter_to_string(&format!("output_strategy_{}.tmx", tuid), &options);
+
+            assert_eq!(1, output.matches("<tu ").count(), "{}", strategy);
+            assert!(output.contains(&format!("<tu tuid=\"{}\"", tuid)), "{}", strategy);
+        }
+    }
+
+    #[test]
+    fn test_filter_date_variants() {
+        // tuid 2 has a truncated creationdate, which counts as missing
+        for (strategy, tuid) in [("newest", "3"), ("oldest", "2"), ("newest-changed", "3"), ("last-used", "1")] {
+            let options = FilterOptions {
+                key: "source".parse().unwrap(),
+                strategy: strategy.parse().unwrap(),
+                ..Default::default()
+            };
+            let output = format!("output_dates_{}.tmx", strategy);
+            filter("test-data/filter/dates.tmx", &output, &options).unwrap();
+            let result = std::fs::read_to_string(&output).unwrap();
+            std::fs::remove_file(&output).unwrap();
+
+            assert_eq!(1, result.matches("<tu ").count(), "{}", strategy);
+            assert!(result.contains(&format!("<tu tuid=\"{}\"", tuid)), "{}", strategy);
+        }
+    }
+
+    #[test]
+    fn test_filter_merge_losers() {
+        let options = FilterOptions {
+            key: "source".parse().unwrap(),
+            merge_losers: true,
+            ..Default::default()
+        };
+        let output = filter_to_string("output_merge_losers.tmx", &options);
+
+        assert!(output.contains(&[
+            "<tu tuid=\"3\" creationdate=\"20170323T152429Z\" creationid=\"Manager\">",
+            "            <prop type=\"tmgr:markup\">OTMXUXLF</prop>",
+            "            <prop type=\"tmgr:docname\">docname2</prop>",
+            "            <prop type=\"tmgr:context\">context2</prop>",
+            "            <prop type=\"tmgr:docname\">docname</prop>",
+            "            <prop type=\"tmgr:context\">context1</prop>",
+            "            <prop type=\"tmgr:context\">context3</prop>",
+            "            <tuv xml:lang=\"de\">",
+        ].join("\n")));
+    }
+
+    #[test]
+    fn test_filter_report() {
+        let options = FilterOptions {
+            key: "source,creationid".parse().unwrap(),
+            report: Some(ReportOptions { path: "output_report.json".to_string(), format: ReportFormat::Json }),
+            ..Default::default()
+        };
+        filter_to_string("output_report.tmx", &options);
+
+        let report: serde_json::Value = serde_json::from_str(&std::fs::read_to_string("output_report.json").unwrap()).unwrap();
+        std::fs::remove_file("output_report.json").unwrap();
+
+        // tuid 1, 2, 2, 2 and 3 share the source and the author
+        let duplicates = report["duplicates"].as_array().unwrap();
+        assert_eq!(1, duplicates.len());
+        assert_eq!("3", duplicates[0]["kept"]["tuid"]);
+        assert_eq!("MANAGER", duplicates[0]["key"]["creationid"].as_str().unwrap().to_ascii_uppercase());
+        assert_eq!(4, duplicates[0]["dropped"].as_array().unwrap().len());
+
+        // tuid 3 and 4 survive with different targets
+        let conflicts = report["conflicts"].as_array().unwrap();
+        assert_eq!(1, conflicts.len());
+        assert_eq!("Unser schönes 4 Segment", conflicts[0]["source"]);
+        assert_eq!(2, conflicts[0]["tus"].as_array().unwrap().len());
+    }
+
+    #[test]
+    fn test_filter_order() {
+        let tuids = |output: &str| -> Vec<String> {
+            output.match_indices("<tu tuid=\"").map(|(i, m)| output[i + m.len()..i + m.len() + 1].to_string()).collect()
+        };
+
+        for (order, expected) in [
+            ("input", vec!["1", "2", "2", "3", "4"]),
+            ("timestamp", vec!["1", "4", "2", "2", "3"]),
+            ("timestamp-desc", vec!["3", "2", "2", "4", "1"]),
+            ("docname", vec!["1", "2", "2", "4", "3"]),
+        ] {
+            for memory_limit in [None, Some(64)].into_iter().filter(|m| m.is_none() || order != "docname") {
+                let options = FilterOptions {
+                    key: "source,target,creationid,prop:tmgr:docname,prop:tmgr:context".parse().unwrap(),
+                    strategy: Strategy::KeepFirst,
+                    order: order.parse().unwrap(),
+                    memory_limit,
+                    ..Default::default()
+                };
+                let output = filter_to_string(&format!("output_order_{}_{}.tmx", order, memory_limit.is_some()), &options);
+
+                assert_eq!(expected, tuids(&output), "{} {:?}", order, memory_limit);
+            }
+        }
+    }
+}
@@ -0,0 +1,215 @@
+use anyhow::{Context, Result};
+use regex::Regex;
+use std::io::Write;
+use std::str::FromStr;
+
+use crate::element::Element;
+use crate::io::{create_output, open_input};
+use crate::reader::{TmxItem, TmxReader};
+use crate::tu::{Tu, Tuv};
+use crate::writer::TmxWriter;
+
+/// Which segments of a TU are searched.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Scope {
+    #[default]
+    All,
+    /// Only the source language, the language of the first `<tuv>` in the file.
+    Source,
+    /// Every language but the source language.
+    Target,
+}
+
+impl FromStr for Scope {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "all" => Ok(Scope::All),
+            "source" => Ok(Scope::Source),
+            "target" => Ok(Scope::Target),
+            _ => Err(anyhow::anyhow!("Unknown scope '{}'. Expected all, source or target", s)),
+        }
+    }
+}
+
+/// How inline elements such as `<t5:n/>` appear in the searched text.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Tags {
+    /// Left out: `Our nice 4  segment`.
+    #[default]
+    Strip,
+    /// Written as XML: `Our nice 4 <t5:n id="2" r="..." n="10"/> segment`.
+    Render,
+}
+
+impl FromStr for Tags {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "strip" => Ok(Tags::Strip),
+            "render" => Ok(Tags::Render),
+            _ => Err(anyhow::anyhow!("Unknown tag mode '{}'. Expected strip or render", s)),
+        }
+    }
+}
+
+pub struct GrepOptions {
+    pub pattern: Regex,
+    pub scope: Scope,
+    pub tags: Tags,
+}
+
+/// A segment that matched.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Match {
+    pub tuid: String,
+    pub lang: String,
+    pub docname: String,
+    pub text: String,
+}
+
+impl GrepOptions {
+    /// The segments of `tu` that match, in document order.
+    pub fn matches(&self, tu: &Tu, source_lang: Option<&str>) -> Vec<Match> {
+        tu.tuvs.iter()
+            .filter(|tuv| match self.scope {
+                Scope::All => true,
+                Scope::Source => Some(tuv.lang.as_str()) == source_lang,
+                Scope::Target => Some(tuv.lang.as_str()) != source_lang,
+            })
+            .map(|tuv| (tuv, self.text(tuv)))
+            .filter(|(_, text)| self.pattern.is_match(text))
+            .map(|(tuv, text)| Match {
+                tuid: tu.attribute("tuid").unwrap_or_default().to_string(),
+                lang: tuv.lang.clone(),
+                docname: tu.prop("tmgr:docname").unwrap_or_default().to_string(),
+                text,
+            })
+            .collect()
+    }
+
+    fn text(&self, tuv: &Tuv) -> String {
+        match self.tags {
+            Tags::Strip => tuv.seg_text(),
+            Tags::Render => tuv.seg_markup(),
+        }
+    }
+}
+
+/// Prints one tab-separated line per matching segment: tuid, language, docname and text.
+/// Returns the number of matching TUs.
+pub fn grep(input: &str, out: &mut impl Write, options: &GrepOptions) -> Result<usize> {
+    let reader = TmxReader::new(open_input(input).context("Cannot open input file")?);
+
+    let mut source_lang: Option<String> = None;
+    let mut count = 0;
+
+    for tu in reader.tus() {
+        let tu = tu?;
+        if source_lang.is_none() {
+            source_lang = tu.tuvs.first().map(|tuv| tuv.lang.clone());
+        }
+
+        let matches = options.matches(&tu, source_lang.as_deref());
+        if !matches.is_empty() {
+            count += 1;
+        }
+        for m in matches {
+            // keep one line per segment
+            let text = m.text.replace(['\r', '\n'], " ");
+            writeln!(out, "{}\t{}\t{}\t{}", m.tuid, m.lang, m.docname, text)?;
+        }
+    }
+
+    out.flush()?;
+    Ok(count)
+}
+
+/// Writes the TUs with a matching segment as a TMX file. Returns the number of matching TUs.
+pub fn grep_tmx(input: &str, output: &str, options: &GrepOptions) -> Result<usize> {
+    let mut reader = TmxReader::new(open_input(input).context("Cannot open input file")?);
+
+    let mut writer = TmxWriter::new(create_output(output).context("Cannot create output file")?);
+
+    let mut source_lang: Option<String> = None;
+    let mut count = 0;
+
+    while let Some(item) = reader.read_item()? {
+        match item {
+            TmxItem::Tu(tu) => {
+                if source_lang.is_none() {
+                    source_lang = tu.tuvs.first().map(|tuv| tuv.lang.clone());
+                }
+
+                if options.matches(&tu, source_lang.as_deref()).is_empty() {
+                    writer.drop_next_whitespace();
+                } else {
+                    writer.write_tu(&tu)?;
+                    count += 1;
+                }
+            }
+            TmxItem::Event(ev) => writer.write_event(ev)?,
+        }
+    }
+
+    writer.into_inner().finish()?;
+    Ok(count)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pretty_assertions::assert_eq;
+    use regex::RegexBuilder;
+
+    fn options(pattern: &str, scope: Scope, tags: Tags) -> GrepOptions {
+        GrepOptions {
+            pattern: RegexBuilder::new(pattern).case_insensitive(true).build().unwrap(),
+            scope,
+            tags,
+        }
+    }
+
+    #[test]
+    fn test_grep() {
+        let mut out = Vec::new();
+        let count = grep("test-data/grep/test.tmx", &mut out, &options(r"segment \d$", Scope::All, Tags::Strip)).unwrap();
+
+        assert_eq!(4, count);
+        assert_eq!(
+            [
+                "2\ten\tdocname\tOur nice 4  segment 1",
+                "2\ten\tdocname\tOur nice 4  segment 1",
+                "2\ten\tdocname\tOur nice 4  segment 1",
+                "3\ten\tdocname2\tOur nice 4  segment 2",
+                "",
+            ].join("\n"),
+            String::from_utf8(out).unwrap()
+        );
+    }
+
+    #[test]
+    fn test_grep_scope_and_tags() {
+        let count = |pattern, scope, tags| grep("test-data/grep/test.tmx", &mut Vec::new(), &options(pattern, scope, tags)).unwrap();
+
+        assert_eq!(6, count("SEGMENT", Scope::All, Tags::Strip));
+        assert_eq!(6, count("segment", Scope::Source, Tags::Strip));
+        assert_eq!(0, count("unser", Scope::Target, Tags::Strip));
+        assert_eq!(0, count(r#"n="10""#, Scope::All, Tags::Strip));
+        assert_eq!(6, count(r#"<t5:n id="2" [^>]*n="10"/>"#, Scope::Target, Tags::Render));
+    }
+
+    #[test]
+    fn test_grep_tmx() {
+        let output = "test-data/grep/output_grep.tmx";
+        let count = grep_tmx("test-data/grep/test.tmx", output, &options("segment 2", Scope::Target, Tags::Strip)).unwrap();
+        let result = std::fs::read_to_string(output).unwrap();
+        std::fs::remove_file(output).unwrap();
+
+        assert_eq!(1, count);
+        assert_eq!(1, result.matches("<tu ").count());
+        assert!(result.contains("<tu tuid=\"3\" creationdate=\"20170323T152429Z\" creationid=\"Manager\">"));
+    }
+}
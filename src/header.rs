@@ -0,0 +1,179 @@
+use anyhow::Result;
+use quick_xml::events::{BytesEnd, BytesText, Event};
+use std::io::BufRead;
+
+use crate::element::{Children, Element};
+use crate::reader::{TmxItem, TmxReader};
+use crate::tu::{attributes, Prop};
+use crate::whitespace::is_xml_whitespace;
+
+/// The `<header>` of a TMX file: the typed attributes, props and notes plus every raw event.
+#[derive(Clone, Debug)]
+pub struct Header {
+    pub attributes: Vec<(String, String)>,
+    pub props: Vec<Prop>,
+    pub notes: Vec<String>,
+    pub events: Vec<Event<'static>>,
+    /// Whitespace in front of `<header>`, used to indent children added to an empty `<header/>`.
+    pub indent: String,
+}
+
+impl Header {
+    /// Builds the typed view from the raw events of a `<header>` element.
+    pub fn from_events(events: Vec<Event<'static>>) -> Header {
+        let mut header = Header {
+            attributes: Vec::new(),
+            props: Vec::new(),
+            notes: Vec::new(),
+            events: Vec::new(),
+            indent: "\n    ".to_string(),
+        };
+
+        let mut depth = 0;
+        let mut children = Children::default();
+
+        for ev in &events {
+            match ev {
+                Event::Start(e) | Event::Empty(e) if depth == 0 => {
+                    header.attributes = attributes(e);
+                    depth += matches!(ev, Event::Start(_)) as usize;
+                }
+                Event::Start(e) => {
+                    if depth == 1 {
+                        children.start(e);
+                    }
+                    depth += 1;
+                }
+                Event::End(e) => {
+                    depth -= 1;
+                    if depth == 1 {
+                        children.end(e);
+                    }
+                }
+                _ => children.text(ev),
+            }
+        }
+
+        header.props = children.props;
+        header.notes = children.notes;
+        header.events = events;
+        header
+    }
+
+    /// Reads the rest of a `<header>` whose start (or empty) event was just returned by `reader`.
+    pub fn read<R: BufRead>(reader: &mut TmxReader<R>, start: Event<'static>) -> Result<Header> {
+        let mut events = vec![start];
+
+        if matches!(events[0], Event::Start(_)) {
+            loop {
+                match reader.read_item()? {
+                    Some(TmxItem::Event(ev)) => {
+                        let end = matches!(ev, Event::End(ref e) if e.name().as_ref() == b"header");
+                        events.push(ev);
+                        if end {
+                            break;
+                        }
+                    }
+                    Some(TmxItem::Tu(_)) => return Err(anyhow::anyhow!("Malformed TMX: <tu> inside <header>")),
+                    None => return Err(anyhow::anyhow!("Malformed TMX: <header> not closed")),
+                }
+            }
+        }
+
+        Ok(Header::from_events(events))
+    }
+}
+
+/// Props and notes go after the last child.
+impl Element for Header {
+    fn attributes(&self) -> &[(String, String)] {
+        &self.attributes
+    }
+
+    fn attributes_mut(&mut self) -> &mut Vec<(String, String)> {
+        &mut self.attributes
+    }
+
+    fn props_mut(&mut self) -> &mut Vec<Prop> {
+        &mut self.props
+    }
+
+    fn notes_mut(&mut self) -> &mut Vec<String> {
+        &mut self.notes
+    }
+
+    fn events_mut(&mut self) -> &mut Vec<Event<'static>> {
+        &mut self.events
+    }
+
+    fn insert_child(&mut self, child: Vec<Event<'static>>) {
+        // <header/> becomes <header></header>, closed on its own line
+        if let Some(Event::Empty(e)) = self.events.first() {
+            let end = BytesEnd::new(String::from_utf8_lossy(e.name().as_ref()).into_owned());
+            self.events = vec![
+                Event::Start(e.clone()),
+                Event::Text(BytesText::from_escaped(self.indent.clone())),
+                Event::End(end),
+            ];
+        }
+
+        // indented like the first child, or one level deeper than <header>
+        let has_children = self.events.iter().skip(1).any(|ev| matches!(ev, Event::Start(_) | Event::Empty(_)));
+        let indent = match self.events.get(1) {
+            Some(Event::Text(t)) if has_children && is_xml_whitespace(t.as_ref()) => t.clone(),
+            _ => BytesText::from_escaped(format!("{}    ", self.indent)),
+        };
+
+        // in front of the whitespace before </header>
+        let mut at = self.events.len().saturating_sub(1);
+        if at > 1 && matches!(&self.events[at - 1], Event::Text(t) if is_xml_whitespace(t.as_ref())) {
+            at -= 1;
+        }
+
+        self.events.splice(at..at, std::iter::once(Event::Text(indent)).chain(child));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pretty_assertions::assert_eq;
+    use quick_xml::Writer;
+
+    fn read(xml: &str) -> Header {
+        let mut reader = TmxReader::new(xml.as_bytes());
+        loop {
+            match reader.read_item().unwrap() {
+                Some(TmxItem::Event(ev @ (Event::Start(_) | Event::Empty(_)))) => return Header::read(&mut reader, ev).unwrap(),
+                Some(_) => {}
+                None => panic!("no header"),
+            }
+        }
+    }
+
+    fn write(header: &Header) -> String {
+        let mut writer = Writer::new(Vec::new());
+        for ev in &header.events {
+            writer.write_event(ev.borrow()).unwrap();
+        }
+        String::from_utf8(writer.into_inner()).unwrap()
+    }
+
+    #[test]
+    fn test_header_add_children() {
+        let mut header = read("<header srclang=\"de\" o-tmf=\"t5memory\">\n        <prop type=\"a\">1</prop>\n    </header>");
+        assert_eq!(Some("de"), header.attribute("srclang"));
+        assert_eq!(vec![Prop { prop_type: "a".to_string(), value: "1".to_string() }], header.props);
+
+        header.add_note("merged".to_string());
+        header.set_attribute("creationtool", "tmx-utils");
+        assert_eq!(
+            "<header srclang=\"de\" o-tmf=\"t5memory\" creationtool=\"tmx-utils\">\n        <prop type=\"a\">1</prop>\n        <note>merged</note>\n    </header>",
+            write(&header)
+        );
+
+        let mut header = read("<header srclang=\"de\"/>");
+        header.add_prop(Prop { prop_type: "b".to_string(), value: "x & y".to_string() });
+        assert_eq!("<header srclang=\"de\">\n        <prop type=\"b\">x &amp; y</prop>\n    </header>", write(&header));
+    }
+}
@@ -0,0 +1,254 @@
+use anyhow::{Context, Result};
+use quick_xml::events::{BytesStart, Event};
+use serde_json::json;
+use std::collections::{BTreeMap, HashMap};
+use std::fmt;
+use std::io::Write;
+
+use crate::element::Element;
+use crate::io::open_input;
+use crate::namespace::{Namespaces, PlaceholderHandler};
+use crate::reader::TmxReader;
+use crate::t5n::base64_decode;
+use crate::tu::Tu;
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum TagProblem {
+    /// `r` is missing, not base64 or not UTF-8 once decoded.
+    Undecodable { r: Option<String> },
+    /// A target placeholder has the `id` of a source placeholder but another `n`.
+    Inconsistent { source_n: String, target_n: String },
+}
+
+impl fmt::Display for TagProblem {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            TagProblem::Undecodable { r: None } => write!(f, "no r attribute"),
+            TagProblem::Undecodable { r: Some(r) } => write!(f, "undecodable r \"{}\"", r),
+            TagProblem::Inconsistent { source_n, target_n } => {
+                write!(f, "n \"{}\", the source has n \"{}\"", target_n, source_n)
+            }
+        }
+    }
+}
+
+/// A `<t5:n>` placeholder with a problem, and where it is.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct TagIssue {
+    /// Number of the TU in the file, from 0.
+    pub position: usize,
+    pub tuid: Option<String>,
+    pub lang: String,
+    pub id: Option<String>,
+    pub problem: TagProblem,
+}
+
+impl fmt::Display for TagIssue {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match &self.tuid {
+            Some(tuid) => write!(f, "tuid {}", tuid)?,
+            None => write!(f, "TU #{}", self.position)?,
+        }
+        write!(f, " ({})", self.lang)?;
+        if let Some(id) = &self.id {
+            write!(f, ", id {}", id)?;
+        }
+        write!(f, ": {}", self.problem)
+    }
+}
+
+/// What `inspect_tags` found in the `<t5:n>` placeholders of a file.
+#[derive(Clone, Debug, Default)]
+pub struct TagInspection {
+    pub tags: usize,
+    /// TUs with at least one placeholder.
+    pub tus: usize,
+    /// Number of placeholders of every decoded `r`.
+    pub rules: BTreeMap<String, usize>,
+    /// Number of placeholders of every `n` value.
+    pub values: BTreeMap<String, usize>,
+    pub issues: Vec<TagIssue>,
+}
+
+impl TagInspection {
+    fn add(&mut self, tu: &Tu, position: usize, source_lang: Option<&str>, namespaces: &Namespaces) {
+        // the placeholders of every <tuv>
+        let mut tags: Vec<Vec<(&BytesStart, &PlaceholderHandler)>> = vec![Vec::new(); tu.tuvs.len()];
+        let mut tuv = None;
+        let mut depth = 0;
+        for (ev, placeholder) in tu.events.iter().zip(namespaces.placeholders(tu)) {
+            match (ev, placeholder) {
+                (Event::Start(e) | Event::Empty(e), _) if depth == 1 && e.name().as_ref() == b"tuv" => {
+                    tuv = Some(tuv.map_or(0, |i| i + 1));
+                }
+                (Event::Empty(e), Some(handler)) => {
+                    if let Some(tags) = tuv.and_then(|i| tags.get_mut(i)) {
+                        tags.push((e, handler));
+                    }
+                }
+                _ => {}
+            }
+            match ev {
+                Event::Start(_) => depth += 1,
+                Event::End(_) => depth -= 1,
+                _ => {}
+            }
+        }
+
+        let source = tu.tuvs.iter()
+            .position(|tuv| source_lang.is_some_and(|lang| tuv.lang.eq_ignore_ascii_case(lang)))
+            .unwrap_or(0);
+        // value of every id in the source segment
+        let mut source_ids: HashMap<String, String> = HashMap::new();
+
+        // the source first, so that the targets can be compared with it
+        let order = std::iter::once(source).chain((0..tu.tuvs.len()).filter(|i| *i != source));
+        for i in order {
+            let Some(tuv) = tu.tuvs.get(i) else { continue };
+            for (e, handler) in &tags[i] {
+                self.tags += 1;
+
+                let id = handler.id_of(e);
+                let n = handler.value_of(e).unwrap_or_default();
+                let issue = |problem| TagIssue {
+                    position,
+                    tuid: tu.attribute("tuid").map(str::to_string),
+                    lang: tuv.lang.clone(),
+                    id: id.clone(),
+                    problem,
+                };
+
+                if handler.rule.is_some() {
+                    let r = handler.rule_of(e);
+                    match r.as_deref().and_then(base64_decode).and_then(|rule| String::from_utf8(rule).ok()) {
+                        Some(rule) => *self.rules.entry(rule).or_default() += 1,
+                        None => self.issues.push(issue(TagProblem::Undecodable { r })),
+                    }
+                }
+                *self.values.entry(n.clone()).or_default() += 1;
+
+                match (&id, i == source) {
+                    (Some(id), true) => {
+                        source_ids.insert(id.clone(), n);
+                    }
+                    (Some(id), false) => {
+                        if let Some(source_n) = source_ids.get(id).filter(|source_n| **source_n != n) {
+                            let problem = TagProblem::Inconsistent { source_n: source_n.clone(), target_n: n };
+                            self.issues.push(issue(problem));
+                        }
+                    }
+                    (None, _) => {}
+                }
+            }
+        }
+
+        self.tus += tags.iter().any(|tags| !tags.is_empty()) as usize;
+    }
+
+    /// A summary with the counts, most frequent first, and one line per issue.
+    pub fn write_text(&self, out: &mut impl Write) -> Result<()> {
+        writeln!(out, "{} t5:n tags in {} TUs", self.tags, self.tus)?;
+        for (title, counts) in [("Rules", &self.rules), ("Values", &self.values)] {
+            writeln!(out, "\n{}:", title)?;
+            for (count, name) in by_count(counts) {
+                writeln!(out, "{:>8}  {}", count, name)?;
+            }
+        }
+        writeln!(out, "\nIssues: {}", self.issues.len())?;
+        for issue in &self.issues {
+            writeln!(out, "  {}", issue)?;
+        }
+        Ok(())
+    }
+
+    pub fn write_json(&self, out: &mut impl Write) -> Result<()> {
+        let counts = |counts: &BTreeMap<String, usize>, name: &str| -> Vec<serde_json::Value> {
+            by_count(counts).into_iter().map(|(count, value)| json!({ name: value, "count": count })).collect()
+        };
+        let issues: Vec<serde_json::Value> = self.issues.iter().map(|issue| {
+            let mut value = json!({
+                "position": issue.position,
+                "tuid": issue.tuid,
+                "lang": issue.lang,
+                "id": issue.id,
+            });
+            match &issue.problem {
+                TagProblem::Undecodable { r } => {
+                    value["problem"] = json!("undecodable");
+                    value["r"] = json!(r);
+                }
+                TagProblem::Inconsistent { source_n, target_n } => {
+                    value["problem"] = json!("inconsistent");
+                    value["source_n"] = json!(source_n);
+                    value["n"] = json!(target_n);
+                }
+            }
+            value
+        }).collect();
+
+        serde_json::to_writer_pretty(&mut *out, &json!({
+            "tags": self.tags,
+            "tus": self.tus,
+            "rules": counts(&self.rules, "rule"),
+            "values": counts(&self.values, "n"),
+            "issues": issues,
+        }))?;
+        writeln!(out)?;
+        Ok(())
+    }
+}
+
+// most frequent first, ties by name
+fn by_count(counts: &BTreeMap<String, usize>) -> Vec<(usize, &str)> {
+    let mut sorted: Vec<(usize, &str)> = counts.iter().map(|(name, count)| (*count, name.as_str())).collect();
+    sorted.sort_by(|a, b| b.0.cmp(&a.0).then(a.1.cmp(b.1)));
+    sorted
+}
+
+/// Decodes the `r` attribute of every `<t5:n>` of `input`, and the rule of every other placeholder of `namespaces`,
+/// and counts the rules and values. Flags placeholders whose rule cannot be decoded, and target placeholders whose
+/// value differs from that of the source placeholder with the same `id`. The language of the first `<tuv>` in the
+/// file is the source language.
+pub fn inspect_tags(input: &str, namespaces: &Namespaces) -> Result<TagInspection> {
+    let reader = TmxReader::new(open_input(input).context("Cannot open input file")?);
+
+    let mut inspection = TagInspection::default();
+    let mut source_lang: Option<String> = None;
+    for (position, tu) in reader.tus().enumerate() {
+        let tu = tu?;
+        if source_lang.is_none() {
+            source_lang = tu.tuvs.first().map(|tuv| tuv.lang.clone());
+        }
+        inspection.add(&tu, position, source_lang.as_deref(), namespaces);
+    }
+    Ok(inspection)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pretty_assertions::assert_eq;
+
+    #[test]
+    fn test_inspect_tags() {
+        let inspection = inspect_tags("test-data/inspect/test.tmx", &Namespaces::default()).unwrap();
+
+        assert_eq!(inspection.tags, 7);
+        assert_eq!(inspection.tus, 3);
+        assert_eq!(inspection.rules.get("default Y-m-d"), Some(&2));
+        assert_eq!(inspection.rules.get("x"), Some(&4));
+        assert_eq!(inspection.values.get("10"), Some(&3));
+
+        let issues: Vec<String> = inspection.issues.iter().map(ToString::to_string).collect();
+        assert_eq!(issues, vec![
+            "tuid 2 (en), id 1: n \"11\", the source has n \"10\"",
+            "TU #2 (de), id 1: undecodable r \"e!==\"",
+        ]);
+
+        let mut json = Vec::new();
+        inspection.write_json(&mut json).unwrap();
+        let json: serde_json::Value = serde_json::from_slice(&json).unwrap();
+        assert_eq!(json["rules"][0], json!({ "rule": "x", "count": 4 }));
+        assert_eq!(json["issues"][0]["problem"], "inconsistent");
+    }
+}
@@ -0,0 +1,210 @@
+use anyhow::{Context, Result};
+use flate2::read::MultiGzDecoder;
+use flate2::write::GzEncoder;
+use std::fs::File;
+use std::io::{BufRead, BufReader, BufWriter, Seek, SeekFrom, Write};
+use xz2::read::XzDecoder;
+use xz2::write::XzEncoder;
+
+/// Compression formats read and written transparently.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Compression {
+    Gzip,
+    Zstd,
+    Xz,
+}
+
+/// The path that stands for stdin as an input and for stdout as an output.
+pub const STDIO: &str = "-";
+
+impl Compression {
+    /// Recognises a compressed stream by its first bytes.
+    pub fn from_magic(bytes: &[u8]) -> Option<Compression> {
+        if bytes.starts_with(&[0x1f, 0x8b]) {
+            Some(Compression::Gzip)
+        } else if bytes.starts_with(&[0x28, 0xb5, 0x2f, 0xfd]) {
+            Some(Compression::Zstd)
+        } else if bytes.starts_with(&[0xfd, b'7', b'z', b'X', b'Z', 0x00]) {
+            Some(Compression::Xz)
+        } else {
+            None
+        }
+    }
+
+    /// The compression implied by a `.gz`, `.zst` or `.xz` file name.
+    pub fn from_path(path: &str) -> Option<Compression> {
+        let path = path.to_ascii_lowercase();
+        if path.ends_with(".gz") {
+            Some(Compression::Gzip)
+        } else if path.ends_with(".zst") {
+            Some(Compression::Zstd)
+        } else if path.ends_with(".xz") {
+            Some(Compression::Xz)
+        } else {
+            None
+        }
+    }
+}
+
+/// The compression of the file at `path`, judged by its magic bytes, or by its extension when it is too short to tell.
+pub fn detect_compression(path: &str) -> Result<Option<Compression>> {
+    let mut file = BufReader::new(File::open(path).context(format!("Cannot open {}", path))?);
+    let magic = file.fill_buf()?;
+
+    Ok(match Compression::from_magic(magic) {
+        None if magic.len() < 6 => Compression::from_path(path),
+        compression => compression,
+    })
+}
+
+/// Opens `path` for reading, or stdin when it is `-`; gzip, zstd and xz input is decompressed while it is read.
+pub fn open_input(path: &str) -> Result<Box<dyn BufRead>> {
+    if path == STDIO {
+        let mut stdin = BufReader::new(std::io::stdin());
+        let compression = Compression::from_magic(stdin.fill_buf().context("Cannot read stdin")?);
+        return decompress(stdin, compression);
+    }
+
+    let compression = detect_compression(path)?;
+    let file = File::open(path).context(format!("Cannot open {}", path))?;
+    decompress(BufReader::new(file), compression)
+}
+
+fn decompress<R: BufRead + 'static>(reader: R, compression: Option<Compression>) -> Result<Box<dyn BufRead>> {
+    Ok(match compression {
+        None => Box::new(reader),
+        Some(Compression::Gzip) => Box::new(BufReader::new(MultiGzDecoder::new(reader))),
+        Some(Compression::Zstd) => Box::new(BufReader::new(zstd::Decoder::with_buffer(reader)?)),
+        Some(Compression::Xz) => Box::new(BufReader::new(XzDecoder::new_multi_decoder(reader))),
+    })
+}
+
+/// Opens `path` as a plain file that can be read more than once; stdin and compressed files are
+/// copied, decompressed, to a temporary file first.
+pub fn open_seekable(path: &str) -> Result<File> {
+    if path != STDIO && detect_compression(path)?.is_none() {
+        return File::open(path).context(format!("Cannot open {}", path));
+    }
+
+    let mut file = tempfile::tempfile().context("Cannot create temporary file")?;
+    std::io::copy(&mut open_input(path)?, &mut file).context(format!("Cannot read {}", path))?;
+    file.seek(SeekFrom::Start(0))?;
+    Ok(file)
+}
+
+/// Copies stdin, decompressed, to a named temporary file, for inputs that are opened more than once.
+pub fn stdin_to_temp() -> Result<tempfile::NamedTempFile> {
+    let mut file = tempfile::NamedTempFile::new().context("Cannot create temporary file")?;
+    std::io::copy(&mut open_input(STDIO)?, &mut file).context("Cannot read stdin")?;
+    Ok(file)
+}
+
+type Sink = BufWriter<Box<dyn Write>>;
+
+/// A file being written, or stdout; files are compressed on the fly when their name ends in `.gz`, `.zst` or `.xz`.
+///
+/// Call [`Output::finish`] when done: it writes the end of the compressed stream and reports errors
+/// that dropping the output would ignore.
+pub enum Output {
+    Plain(Sink),
+    Gzip(GzEncoder<Sink>),
+    Zstd(zstd::Encoder<'static, Sink>),
+    Xz(XzEncoder<Sink>),
+}
+
+/// Creates `path`, compressed according to its extension, or writes to stdout when it is `-`.
+pub fn create_output(path: &str) -> Result<Output> {
+    let sink: Box<dyn Write> = match path {
+        STDIO => Box::new(std::io::stdout()),
+        _ => Box::new(File::create(path).context(format!("Cannot create {}", path))?),
+    };
+    compress(BufWriter::new(sink), Compression::from_path(path))
+}
+
+/// Writes at the end of the existing file `path`, compressed like it. A compressed file gets another stream,
+/// which [`open_input`] reads as the continuation of the first.
+pub fn append_output(path: &str) -> Result<Output> {
+    let compression = detect_compression(path)?;
+    let file = File::options().append(true).open(path).context(format!("Cannot open {}", path))?;
+    compress(BufWriter::new(Box::new(file)), compression)
+}
+
+fn compress(sink: Sink, compression: Option<Compression>) -> Result<Output> {
+    Ok(match compression {
+        None => Output::Plain(sink),
+        Some(Compression::Gzip) => Output::Gzip(GzEncoder::new(sink, flate2::Compression::default())),
+        Some(Compression::Zstd) => Output::Zstd(zstd::Encoder::new(sink, 0)?),
+        Some(Compression::Xz) => Output::Xz(XzEncoder::new(sink, 6)),
+    })
+}
+
+impl Output {
+    pub fn finish(self) -> Result<()> {
+        let mut file = match self {
+            Output::Plain(file) => file,
+            Output::Gzip(encoder) => encoder.finish()?,
+            Output::Zstd(encoder) => encoder.finish()?,
+            Output::Xz(encoder) => encoder.finish()?,
+        };
+        file.flush()?;
+        Ok(())
+    }
+}
+
+impl Write for Output {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        match self {
+            Output::Plain(w) => w.write(buf),
+            Output::Gzip(w) => w.write(buf),
+            Output::Zstd(w) => w.write(buf),
+            Output::Xz(w) => w.write(buf),
+        }
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        match self {
+            Output::Plain(w) => w.flush(),
+            Output::Gzip(w) => w.flush(),
+            Output::Zstd(w) => w.flush(),
+            Output::Xz(w) => w.flush(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pretty_assertions::assert_eq;
+    use std::io::Read;
+
+    #[test]
+    fn test_compressed_round_trip() {
+        let dir = tempfile::tempdir().unwrap();
+        let content = std::fs::read_to_string("test-data/trim/test.tmx").unwrap();
+
+        for (name, compression) in [("a.tmx", None), ("a.tmx.gz", Some(Compression::Gzip)), ("a.tmx.zst", Some(Compression::Zstd)), ("a.tmx.xz", Some(Compression::Xz))] {
+            let path = dir.path().join(name).to_string_lossy().to_string();
+
+            let mut output = create_output(&path).unwrap();
+            output.write_all(content.as_bytes()).unwrap();
+            output.finish().unwrap();
+
+            // detected by content, not by name
+            let renamed = format!("{}.data", path);
+            std::fs::rename(&path, &renamed).unwrap();
+            assert_eq!(compression, detect_compression(&renamed).unwrap(), "{}", name);
+
+            let mut read = String::new();
+            open_input(&renamed).unwrap().read_to_string(&mut read).unwrap();
+            assert_eq!(content, read, "{}", name);
+
+            let mut output = append_output(&renamed).unwrap();
+            output.write_all(b"<!-- appended -->").unwrap();
+            output.finish().unwrap();
+
+            let mut read = String::new();
+            open_input(&renamed).unwrap().read_to_string(&mut read).unwrap();
+            assert_eq!(format!("{}<!-- appended -->", content), read, "{}", name);
+        }
+    }
+}
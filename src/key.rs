@@ -0,0 +1,212 @@
+use anyhow::Result;
+use quick_xml::events::Event;
+use std::fmt;
+use std::hash::{Hash, Hasher};
+use std::str::FromStr;
+
+use crate::element::Element;
+use crate::tu::{is_user_id, Tu};
+
+/// One component of the key that decides whether two TUs are duplicates.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum KeyField {
+    /// Segment content of the source language, inline tags included.
+    Source,
+    /// Segment content of every other language, inline tags included.
+    Target,
+    /// The set of target languages.
+    TargetLang,
+    /// A `<tu>` attribute such as `tuid`, `creationid` or `changeid`.
+    Attribute(String),
+    /// The value of a `<prop type="...">`.
+    Prop(String),
+}
+
+/// Ordered list of [`KeyField`]s, written as `source,target,creationid,prop:tmgr:docname`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct KeySpec {
+    pub fields: Vec<KeyField>,
+}
+
+impl Default for KeySpec {
+    /// Source text, author, document and context: the key t5memory uses.
+    fn default() -> Self {
+        KeySpec {
+            fields: vec![
+                KeyField::Source,
+                KeyField::Attribute("creationid".to_string()),
+                KeyField::Prop("tmgr:docname".to_string()),
+                KeyField::Prop("tmgr:context".to_string()),
+            ],
+        }
+    }
+}
+
+impl KeySpec {
+    pub fn hash(&self, tu: &Tu, source_lang: Option<&str>) -> u64 {
+        let mut hasher = std::collections::hash_map::DefaultHasher::new();
+
+        for field in &self.fields {
+            // tag every field so that values cannot shift from one field into another
+            field.to_string().hash(&mut hasher);
+
+            match field {
+                KeyField::Source => {
+                    for tuv in tu.tuvs.iter().filter(|tuv| Some(tuv.lang.as_str()) == source_lang) {
+                        hash_seg(&tuv.seg, &mut hasher);
+                    }
+                }
+                KeyField::Target => {
+                    for tuv in tu.tuvs.iter().filter(|tuv| Some(tuv.lang.as_str()) != source_lang) {
+                        hash_seg(&tuv.seg, &mut hasher);
+                    }
+                }
+                KeyField::TargetLang => target_langs(tu, source_lang).hash(&mut hasher),
+                KeyField::Attribute(name) => hash_value(tu.attribute(name), is_user_id(name), &mut hasher),
+                KeyField::Prop(prop_type) => hash_value(tu.prop(prop_type), false, &mut hasher),
+            }
+        }
+
+        hasher.finish()
+    }
+
+    /// The value of every field for `tu`, as text.
+    pub fn values(&self, tu: &Tu, source_lang: Option<&str>) -> Vec<(String, String)> {
+        self.fields.iter().map(|field| {
+            let value = match field {
+                KeyField::Source => source_text(tu, source_lang),
+                KeyField::Target => target_text(tu, source_lang),
+                KeyField::TargetLang => target_langs(tu, source_lang).join(","),
+                KeyField::Attribute(name) => tu.attribute(name).unwrap_or_default().to_string(),
+                KeyField::Prop(prop_type) => tu.prop(prop_type).unwrap_or_default().to_string(),
+            };
+            (field.to_string(), value)
+        }).collect()
+    }
+}
+
+/// Plain text of the source language segments.
+pub fn source_text(tu: &Tu, source_lang: Option<&str>) -> String {
+    let texts: Vec<String> = tu.tuvs.iter()
+        .filter(|tuv| Some(tuv.lang.as_str()) == source_lang)
+        .map(|tuv| tuv.seg_text())
+        .collect();
+    texts.join(" | ")
+}
+
+/// Plain text of all other segments.
+pub fn target_text(tu: &Tu, source_lang: Option<&str>) -> String {
+    let texts: Vec<String> = tu.tuvs.iter()
+        .filter(|tuv| Some(tuv.lang.as_str()) != source_lang)
+        .map(|tuv| tuv.seg_text())
+        .collect();
+    texts.join(" | ")
+}
+
+fn hash_seg(seg: &[Event], hasher: &mut impl Hasher) {
+    for ev in seg {
+        Hash::hash_slice(ev as &[u8], hasher);
+    }
+}
+
+// missing and empty values are the same, "-"
+fn hash_value(value: Option<&str>, case_insensitive: bool, hasher: &mut impl Hasher) {
+    match value {
+        Some(value) if !value.is_empty() && case_insensitive => value.to_ascii_uppercase().hash(hasher),
+        Some(value) if !value.is_empty() => value.hash(hasher),
+        _ => "-".hash(hasher),
+    }
+}
+
+fn target_langs<'a>(tu: &'a Tu, source_lang: Option<&str>) -> Vec<&'a str> {
+    let mut langs: Vec<&str> = tu.tuvs.iter()
+        .map(|tuv| tuv.lang.as_str())
+        .filter(|lang| Some(*lang) != source_lang)
+        .collect();
+    langs.sort_unstable();
+    langs.dedup();
+    langs
+}
+
+impl fmt::Display for KeyField {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            KeyField::Source => write!(f, "source"),
+            KeyField::Target => write!(f, "target"),
+            KeyField::TargetLang => write!(f, "target-lang"),
+            KeyField::Attribute(name) if is_user_id(name) || name == "tuid" => write!(f, "{}", name),
+            KeyField::Attribute(name) => write!(f, "attr:{}", name),
+            KeyField::Prop(prop_type) => write!(f, "prop:{}", prop_type),
+        }
+    }
+}
+
+impl FromStr for KeyField {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s.trim() {
+            "source" => Ok(KeyField::Source),
+            "target" => Ok(KeyField::Target),
+            "target-lang" => Ok(KeyField::TargetLang),
+            "tuid" | "creationid" | "changeid" => Ok(KeyField::Attribute(s.trim().to_string())),
+            field => {
+                if let Some(prop_type) = field.strip_prefix("prop:").filter(|t| !t.is_empty()) {
+                    Ok(KeyField::Prop(prop_type.to_string()))
+                } else if let Some(name) = field.strip_prefix("attr:").filter(|n| !n.is_empty()) {
+                    Ok(KeyField::Attribute(name.to_string()))
+                } else {
+                    Err(anyhow::anyhow!(
+                        "Unknown key field '{}'. Expected source, target, target-lang, tuid, creationid, changeid, attr:<name> or prop:<type>",
+                        field
+                    ))
+                }
+            }
+        }
+    }
+}
+
+impl FromStr for KeySpec {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let fields = s.split(',').map(str::parse).collect::<Result<Vec<KeyField>>>()?;
+        if fields.is_empty() {
+            return Err(anyhow::anyhow!("Key must contain at least one field"));
+        }
+
+        Ok(KeySpec { fields })
+    }
+}
+
+impl fmt::Display for KeySpec {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let fields: Vec<String> = self.fields.iter().map(|field| field.to_string()).collect();
+        write!(f, "{}", fields.join(","))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pretty_assertions::assert_eq;
+
+    #[test]
+    fn test_parse_key_spec() {
+        let key: KeySpec = "source, target,prop:tmgr:docname,creationid,attr:usagecount".parse().unwrap();
+        assert_eq!(
+            vec![
+                KeyField::Source,
+                KeyField::Target,
+                KeyField::Prop("tmgr:docname".to_string()),
+                KeyField::Attribute("creationid".to_string()),
+                KeyField::Attribute("usagecount".to_string()),
+            ],
+            key.fields
+        );
+        assert_eq!("source,target,prop:tmgr:docname,creationid,attr:usagecount", key.to_string());
+
+        assert!("source,author".parse::<KeySpec>().is_err());
+        assert!("prop:".parse::<KeySpec>().is_err());
+    }
+}
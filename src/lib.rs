@@ -0,0 +1,33 @@
+pub mod whitespace;
+
+pub mod tu;
+pub mod header;
+pub mod element;
+pub mod date;
+pub mod reader;
+pub mod writer;
+pub mod io;
+pub mod quarantine;
+pub mod namespace;
+
+pub mod trim;
+pub mod concat;
+pub mod concat_dir;
+pub mod filter;
+pub mod select;
+pub mod grep;
+pub mod replace;
+pub mod pipeline;
+pub mod split;
+pub mod sample;
+pub mod t5n;
+pub mod inspect;
+pub mod validate;
+pub mod key;
+pub mod report;
+
+mod external;
+
+pub use self::reader::{TmxItem, TmxReader};
+pub use self::tu::{Prop, Tu, Tuv};
+pub use self::writer::TmxWriter;
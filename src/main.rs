@@ -1,134 +1,652 @@
 use anyhow::{Context, Result};
-use std::env;
+use clap::{Args, CommandFactory, Parser, Subcommand};
+use clap_complete::Shell;
+use std::io::Write;
+use std::process::ExitCode;
 
-mod whitespace;
+use tmx_utils::trim::{trim, TrimOptions};
+use tmx_utils::concat::{concat, ConcatOptions};
+use tmx_utils::filter;
+use tmx_utils::concat_dir::{concat_dir, DirOptions, SortBy};
+use tmx_utils::io::STDIO;
+use tmx_utils::inspect::inspect_tags;
+use tmx_utils::namespace::Namespaces;
+use tmx_utils::quarantine::Quarantine;
+use tmx_utils::grep::{grep, grep_tmx, GrepOptions, Scope, Tags};
+use tmx_utils::pipeline::{pipeline, read_stages, PipelineOptions, StageSpec};
+use tmx_utils::split::{split, SplitBy};
+use tmx_utils::sample::{parse_ratios, partition, sample, PartitionFormat, PartitionOptions, SampleSize};
+use tmx_utils::key::{KeyField, KeySpec};
+use tmx_utils::replace::{read_rules, replace, ReplaceOptions, Rule};
+use tmx_utils::report::{ReportFormat, ReportOptions};
+use tmx_utils::select::{select, Predicate, SelectOptions};
+use tmx_utils::t5n::{t5n, T5nMode, T5nOptions};
+use tmx_utils::validate::{validate, Severity};
 
-mod attribute;
+/// Utilities for TMX translation memories.
+///
+/// Exit codes: 0 on success, 1 when the command fails, 2 on invalid usage.
+#[derive(Parser)]
+#[command(name = "tmx-utils", version, propagate_version = true)]
+struct Cli {
+    #[command(subcommand)]
+    command: Command,
+}
 
-mod trim;
+#[derive(Subcommand)]
+enum Command {
+    /// Keep a range of the <tu> elements: skip, take, every k-th or the last N
+    ///
+    /// TUs are numbered from 0 in file order. --skip, --take (or --range), --every and --tail apply in this order
+    Trim {
+        /// Input TMX file, - for stdin
+        input: String,
+        /// Output TMX file, - for stdout
+        #[arg(short, long)]
+        output: String,
+        /// Number of <tu> elements to skip, like --skip
+        #[arg(conflicts_with_all = ["skip", "range"])]
+        n: Option<usize>,
+        /// Drop the first N TUs
+        #[arg(long, value_name = "N", conflicts_with = "range")]
+        skip: Option<usize>,
+        /// Keep at most N TUs after the skipped ones
+        #[arg(long, value_name = "N", conflicts_with = "range")]
+        take: Option<usize>,
+        /// Keep TUs FROM to TO - 1, numbered from 0, e.g. 1000..2000; either end may be left out
+        #[arg(long, value_name = "FROM..TO")]
+        range: Option<String>,
+        /// Keep the first of every K TUs
+        #[arg(long, value_name = "K", default_value = "1")]
+        every: usize,
+        /// Keep only the last N of the remaining TUs
+        #[arg(long, value_name = "N")]
+        tail: Option<usize>,
+        /// Convert the <t5:n> placeholders of the TUs kept, like the t5n command: n, r, ph or protect
+        #[arg(long, value_name = "MODE")]
+        t5n: Option<T5nMode>,
+        /// Treat the placeholders of namespace URI like those of HANDLER: t5memory, or
+        /// element=<name>;value=<attribute>[;id=<attribute>][;rule=<attribute>] (repeatable)
+        #[arg(long, num_args = 2, value_names = ["URI", "HANDLER"])]
+        namespace: Vec<String>,
+        /// Write malformed TUs to FILE, with their byte offset and error, and go on instead of failing
+        #[arg(long, value_name = "FILE")]
+        quarantine: Option<String>,
+    },
 
-use self::trim::trim;
+    /// Merge the <tu> elements of several TMX files into the first one
+    Concat {
+        /// Input TMX files, in output order; - reads stdin
+        #[arg(required = true)]
+        inputs: Vec<String>,
+        /// Output TMX file, - for stdout
+        #[arg(short, long)]
+        output: String,
+        #[command(flatten)]
+        flags: ConcatFlags,
+    },
 
-mod concat;
-use self::concat::concat;
+    /// Merge all .tmx files of a directory
+    #[command(alias = "concat_dir")]
+    ConcatDir {
+        /// Directory containing the TMX files
+        input_dir: String,
+        /// Output TMX file, - for stdout
+        #[arg(short, long)]
+        output: String,
+        /// Include .tmx files in subdirectories
+        #[arg(short, long)]
+        recursive: bool,
+        /// Only merge files matching this glob, e.g. 'part*.tmx' (repeatable)
+        #[arg(long, value_name = "GLOB")]
+        include: Vec<String>,
+        /// Leave out files matching this glob (repeatable)
+        #[arg(long, value_name = "GLOB")]
+        exclude: Vec<String>,
+        /// Merge order: name (natural order, part9 before part10) or modified (oldest first)
+        #[arg(long, value_name = "ORDER", default_value = "name")]
+        sort: SortBy,
+        #[command(flatten)]
+        flags: ConcatFlags,
+    },
 
-mod filter;
+    /// Remove duplicate <tu> elements, keeping the most recent one
+    Filter {
+        /// Input TMX file, - for stdin
+        input: String,
+        /// Output TMX file, - for stdout
+        #[arg(short, long)]
+        output: String,
+        /// Comma-separated fields that make two TUs duplicates: source, target, target-lang,
+        /// tuid, creationid, changeid, attr:<name>, prop:<type>
+        /// [default: source,creationid,prop:tmgr:docname,prop:tmgr:context]
+        #[arg(long, value_name = "FIELDS")]
+        key: Option<KeySpec>,
+        /// Leave the author (creationid) out of the default key
+        #[arg(long, conflicts_with = "key")]
+        skip_author: bool,
+        /// Leave the document name (tmgr:docname) out of the default key
+        #[arg(long, conflicts_with = "key")]
+        skip_document: bool,
+        /// Leave the context (tmgr:context) out of the default key
+        #[arg(long, conflicts_with = "key")]
+        skip_context: bool,
+        /// Add the target text to the default key
+        #[arg(long, conflicts_with = "key")]
+        keep_diff_targets: bool,
+        /// Which duplicate survives: newest, oldest, newest-changed, most-used, last-used, first,
+        /// last or author:<id>. Equal candidates resolve to the first one in the file
+        #[arg(long, value_name = "STRATEGY", default_value = "newest")]
+        keep: filter::Strategy,
+        /// Copy the props and notes of dropped duplicates into the surviving TU
+        #[arg(long, conflicts_with = "memory_limit")]
+        merge_losers: bool,
+        /// Order of the surviving TUs: input, timestamp, timestamp-desc, source or docname.
+        /// Ties keep the input order
+        #[arg(long, value_name = "ORDER", default_value = "timestamp")]
+        order: filter::Order,
+        /// Write every duplicate group and every source with conflicting targets to this file
+        #[arg(long, value_name = "FILE", conflicts_with = "memory_limit")]
+        report: Option<String>,
+        /// Format of the report: csv or json [default: json for .json files, csv otherwise]
+        #[arg(long, value_name = "FORMAT", requires = "report")]
+        report_format: Option<ReportFormat>,
+        /// Keep only hashes and byte offsets in memory, spilling to temporary files above this
+        /// many MiB, and re-read the surviving TUs from the input in a second pass
+        #[arg(long, value_name = "MIB")]
+        memory_limit: Option<usize>,
+        /// Convert the <t5:n> placeholders of the surviving TUs, like the t5n command: n, r, ph or protect
+        #[arg(long, value_name = "MODE")]
+        t5n: Option<T5nMode>,
+        /// Treat the placeholders of namespace URI like those of HANDLER: t5memory, or
+        /// element=<name>;value=<attribute>[;id=<attribute>][;rule=<attribute>] (repeatable)
+        #[arg(long, num_args = 2, value_names = ["URI", "HANDLER"])]
+        namespace: Vec<String>,
+        /// Write malformed TUs to FILE, with their byte offset and error, and go on instead of failing
+        #[arg(long, value_name = "FILE")]
+        quarantine: Option<String>,
+    },
 
-mod concat_dir;
-use crate::concat_dir::concat_dir;
+    /// Keep the <tu> elements that match date, author, document, language or tuid conditions
+    Select {
+        /// Input TMX file, - for stdin
+        input: String,
+        /// Output TMX file, - for stdout
+        #[arg(short, long)]
+        output: String,
+        /// Keep TUs matching this condition (repeatable): created:<from>..<to>, changed:<from>..<to>,
+        /// used:<from>..<to>, author:<id>, changer:<id>, docname:<name>, context:<context>,
+        /// prop:<type>=<value>, attr:<name>=<value>, lang:<lang>, tuid:<id> or tuid:<from>..<to>.
+        /// Either end of a range may be left out; the end itself is excluded
+        #[arg(long, value_name = "CONDITION")]
+        include: Vec<Predicate>,
+        /// Drop TUs matching this condition (repeatable), even when they are included
+        #[arg(long, value_name = "CONDITION")]
+        exclude: Vec<Predicate>,
+        /// Keep TUs matching any --include condition instead of all of them
+        #[arg(long)]
+        any: bool,
+    },
 
-fn main() -> Result<()> {
-    let args: Vec<String> = env::args().collect();
+    /// Search the segments with a regular expression
+    Grep {
+        /// Input TMX file, - for stdin
+        input: String,
+        /// Regular expression matched against the segment text
+        pattern: String,
+        /// Match case-insensitively
+        #[arg(short, long)]
+        ignore_case: bool,
+        /// Segments to search: all, source or target
+        #[arg(long = "in", value_name = "SEGMENTS", default_value = "all")]
+        scope: Scope,
+        /// Inline elements such as <t5:n/>: strip leaves them out, render writes them as XML
+        #[arg(long, value_name = "MODE", default_value = "strip")]
+        tags: Tags,
+        /// Write the matching TUs to this TMX file (- for stdout) instead of printing the matching segments
+        #[arg(short, long)]
+        output: Option<String>,
+    },
 
-    // filter out args before the command
-    
+    /// Replace text in the segments with regular expressions, leaving inline elements untouched
+    Replace {
+        /// Input TMX file, - for stdin
+        input: String,
+        /// Output TMX file, - for stdout
+        #[arg(short, long)]
+        output: String,
+        /// Replace PATTERN with REPLACEMENT, which may refer to groups as $1 (repeatable)
+        #[arg(long, num_args = 2, value_names = ["PATTERN", "REPLACEMENT"])]
+        rule: Vec<String>,
+        /// Read more rules from a file with one PATTERN<TAB>REPLACEMENT per line
+        #[arg(long, value_name = "FILE")]
+        rules: Option<String>,
+        /// Match the patterns case-insensitively
+        #[arg(short, long)]
+        ignore_case: bool,
+        /// Only change segments in this language (en also matches en-US)
+        #[arg(long)]
+        lang: Option<String>,
+        /// changeid written to changed TUs
+        #[arg(long, value_name = "ID", default_value = "tmx-utils")]
+        changeid: String,
+    },
 
-    let command = &args[1];
+    /// Split a TMX file into several, each with the original header
+    Split {
+        /// Input TMX file, - for stdin
+        input: String,
+        /// Output file names: {n} is the number of the file, from 1, and {key} the docname, author or
+        /// target language, e.g. part-{n}.tmx or memory-{key}.tmx.gz
+        #[arg(short, long, value_name = "PATTERN")]
+        output: String,
+        /// count:N TUs per file, size:SIZE bytes per file (e.g. 50M), or one file per docname, author
+        /// or target-lang
+        #[arg(long, value_name = "SPLIT")]
+        by: SplitBy,
+    },
 
-    if "trim" == command {
-        if args.len() != 5 {
-            eprintln!("Usage: {} {} <input.tmx> <output.tmx> <N>", args[0], command);
-            std::process::exit(1);
-        }
+    /// Write a reproducible random sample of the <tu> elements
+    Sample {
+        /// Input TMX file, - for stdin
+        input: String,
+        /// Output TMX file, - for stdout
+        #[arg(short, long)]
+        output: String,
+        /// Number of TUs, or a percentage like 5%
+        #[arg(long, value_name = "N|PERCENT")]
+        size: SampleSize,
+        /// The same seed draws the same sample from the same input
+        #[arg(long, default_value = "0")]
+        seed: u64,
+    },
+
+    /// Distribute the <tu> elements over train, dev and test files at random
+    Partition {
+        /// Input TMX file, - for stdin
+        input: String,
+        /// Output file names: {part} is train, dev or test and {lang} the language of a text file,
+        /// e.g. corpus.{part}.tmx or corpus.{part}.{lang}
+        #[arg(short, long, value_name = "PATTERN")]
+        output: String,
+        /// Shares of train, dev and test; a part with 0 is not written
+        #[arg(long, value_name = "TRAIN:DEV:TEST", default_value = "80:10:10", value_parser = parse_ratios)]
+        ratio: [f64; 3],
+        /// The same seed gives the same partition of the same input
+        #[arg(long, default_value = "0")]
+        seed: u64,
+        /// Keep TUs with the same source text in the same part
+        #[arg(long)]
+        same_source: bool,
+        /// tmx, or text: one segment per line, in a file for the source and the first target language
+        #[arg(long, value_name = "FORMAT", default_value = "tmx")]
+        format: PartitionFormat,
+    },
+
+    /// Run several operations on the <tu> elements in a single pass and print the counters of each
+    Pipeline {
+        /// Input TMX file, - for stdin
+        input: String,
+        /// Output TMX file, - for stdout
+        #[arg(short, long)]
+        output: String,
+        /// A stage, applied in the order given (repeatable): skip:N, take:N, select:<condition>,
+        /// reject:<condition>, dedupe[:keep=<strategy>;key=<fields>], replace:<pattern> => <replacement>,
+        /// unprotect, t5n:<mode> or validate[:drop|fail]. Conditions are those of select
+        #[arg(long, value_name = "STAGE")]
+        stage: Vec<StageSpec>,
+        /// Read more stages from a plain text file with one stage per line, written like --stage.
+        /// Empty lines and lines starting with # are skipped
+        #[arg(long, value_name = "FILE")]
+        stages: Option<String>,
+        /// changeid written to TUs changed by a replace stage
+        #[arg(long, value_name = "ID", default_value = "tmx-utils")]
+        changeid: String,
+        /// Treat the placeholders of namespace URI like those of HANDLER: t5memory, or
+        /// element=<name>;value=<attribute>[;id=<attribute>][;rule=<attribute>] (repeatable)
+        #[arg(long, num_args = 2, value_names = ["URI", "HANDLER"])]
+        namespace: Vec<String>,
+    },
+
+    /// Convert the <t5:n> placeholders of t5memory, or protect numbers and variables with them
+    T5n {
+        /// Input TMX file, - for stdin
+        input: String,
+        /// Output TMX file, - for stdout
+        #[arg(short, long)]
+        output: String,
+        /// n: replace each placeholder with its n value; r: with its base64-decoded r attribute;
+        /// ph: turn it into a TMX <ph>; protect: replace numbers and variables with placeholders
+        #[arg(long, value_name = "MODE")]
+        mode: T5nMode,
+        /// What protect replaces [default: numbers, {name}, {{name}}, %s and %d]
+        #[arg(long, value_name = "REGEX")]
+        pattern: Option<String>,
+        /// Treat the placeholders of namespace URI like those of HANDLER: t5memory, or
+        /// element=<name>;value=<attribute>[;id=<attribute>][;rule=<attribute>] (repeatable)
+        #[arg(long, num_args = 2, value_names = ["URI", "HANDLER"])]
+        namespace: Vec<String>,
+    },
+
+    /// Decode the r attribute of every <t5:n> and report the rules, n values and broken placeholders
+    InspectTags {
+        /// Input TMX file, - for stdin
+        input: String,
+        /// Print the findings as JSON
+        #[arg(long)]
+        json: bool,
+        /// Treat the placeholders of namespace URI like those of HANDLER: t5memory, or
+        /// element=<name>;value=<attribute>[;id=<attribute>][;rule=<attribute>] (repeatable)
+        #[arg(long, num_args = 2, value_names = ["URI", "HANDLER"])]
+        namespace: Vec<String>,
+    },
+
+    /// Check the TMX 1.4b rules and print every error and warning with its line and column.
+    /// Fails when there are errors
+    Validate {
+        /// Input TMX file, - for stdin
+        input: String,
+        /// Fail on warnings as well
+        #[arg(long)]
+        strict: bool,
+    },
+
+    /// Print a shell completion script
+    Completions {
+        shell: Shell,
+    },
+}
+
+#[derive(Args)]
+struct ConcatFlags {
+    /// Replace <t5:n> placeholders with their n value
+    #[arg(long)]
+    unprotect: bool,
+    /// Treat the placeholders of namespace URI like those of HANDLER: t5memory, or
+    /// element=<name>;value=<attribute>[;id=<attribute>][;rule=<attribute>] (repeatable)
+    #[arg(long, num_args = 2, value_names = ["URI", "HANDLER"])]
+    namespace: Vec<String>,
+    /// Merge files with different header srclangs, setting srclang="*all*", instead of failing
+    #[arg(long)]
+    mixed_srclang: bool,
+    /// Set creationtool, creationtoolversion and creationdate of the merged header to describe the merge
+    #[arg(long)]
+    regenerate_header: bool,
+    /// Write malformed TUs to FILE, with their input, byte offset and error, and go on instead of failing
+    #[arg(long, value_name = "FILE")]
+    quarantine: Option<String>,
+}
+
+impl ConcatFlags {
+    fn options(self) -> Result<ConcatOptions> {
+        Ok(ConcatOptions {
+            unprotect: self.unprotect,
+            namespaces: namespaces(&self.namespace)?,
+            mixed_srclang: self.mixed_srclang,
+            regenerate_header: self.regenerate_header.then(chrono::Utc::now),
+            quarantine: quarantine(self.quarantine)?,
+        })
+    }
+}
+
+// URI and handler pairs of --namespace
+fn namespaces(pairs: &[String]) -> Result<Namespaces> {
+    let mut namespaces = Namespaces::default();
+    for pair in pairs.chunks(2) {
+        namespaces.uris.push((pair[0].clone(), pair[1].parse()?));
+    }
+    Ok(namespaces)
+}
+
+// the file of --quarantine
+fn quarantine(path: Option<String>) -> Result<Option<Quarantine>> {
+    path.as_deref().map(Quarantine::create).transpose()
+}
 
-        return trim(&args[2], &args[3], args[4].parse().context("N must be an integer")?);
+// completes the quarantine, also when the command failed, and tells how many TUs are in it
+fn finish_quarantine<T>(quarantine: Option<Quarantine>, result: Result<T>) -> Result<()> {
+    if let Some(quarantine) = quarantine {
+        let path = quarantine.path();
+        let count = quarantine.finish()?;
+        eprintln!("{} malformed TUs quarantined in {}", count, path);
     }
+    result.map(|_| ())
+}
+
+fn run(command: Command) -> Result<()> {
+    match command {
+        Command::Trim { input, output, n, skip, take, range, every, tail, t5n, namespace, quarantine: path } => {
+            let mut options = TrimOptions {
+                skip: n.or(skip).unwrap_or(0),
+                take,
+                every,
+                tail,
+                t5n,
+                namespaces: namespaces(&namespace)?,
+                quarantine: quarantine(path)?,
+            };
+            if let Some(range) = range {
+                options = options.with_range(&range)?;
+            }
+            let result = trim(&input, &output, &options);
+            finish_quarantine(options.quarantine, result)
+        }
+
+        Command::Concat { inputs, output, flags } => {
+            let options = flags.options()?;
+            let result = concat(&inputs, &output, &options);
+            finish_quarantine(options.quarantine, result)
+        }
+
+        Command::ConcatDir { input_dir, output, recursive, include, exclude, sort, flags } => {
+            let dir_options = DirOptions { recursive, include, exclude, sort };
+            let options = flags.options()?;
+            let result = concat_dir(&input_dir, &output, &dir_options, &options);
+            finish_quarantine(options.quarantine, result)
+        }
+
+        Command::Filter {
+            input, output, key, skip_author, skip_document, skip_context, keep_diff_targets, keep, merge_losers, order,
+            report, report_format, memory_limit, t5n, namespace, quarantine: path,
+        } => {
+            let key = key.unwrap_or_else(|| {
+                let mut key = KeySpec::default();
+                key.fields.retain(|field| match field {
+                    KeyField::Attribute(name) if name == "creationid" => !skip_author,
+                    KeyField::Prop(prop_type) if prop_type == "tmgr:docname" => !skip_document,
+                    KeyField::Prop(prop_type) if prop_type == "tmgr:context" => !skip_context,
+                    _ => true,
+                });
+                if keep_diff_targets {
+                    key.fields.push(KeyField::Target);
+                }
+                key
+            });
+
+            let options = filter::FilterOptions {
+                key,
+                strategy: keep,
+                merge_losers,
+                order,
+                report: report.map(|path| ReportOptions {
+                    format: report_format.unwrap_or_else(|| ReportFormat::from_path(&path)),
+                    path,
+                }),
+                memory_limit: memory_limit
+                    .map(|mib| mib.checked_mul(1024 * 1024).context(format!("--memory-limit {} MiB is too large", mib)))
+                    .transpose()?,
+                t5n,
+                namespaces: namespaces(&namespace)?,
+                quarantine: None,
+            };
+            // before the quarantine file is created
+            options.check()?;
+            let options = filter::FilterOptions { quarantine: quarantine(path)?, ..options };
+
+            let result = filter::filter(&input, &output, &options);
+            finish_quarantine(options.quarantine, result)
+        }
 
-    if "concat" == command {
-        if args.len() < 5 {
-            eprintln!("Usage: {} {} <output.tmx> <unprotect: true|false> <input1.tmx> [<input2.tmx> ...]", args[0], command);
-            std::process::exit(1);
+        Command::Select { input, output, include, exclude, any } => {
+            select(&input, &output, &SelectOptions { include, exclude, any })
         }
 
-        let output = &args[2];
-        let unprotect = match args[3].as_str() {
-            "true" => true,
-            "false" => false,
-            _ => {
-                eprintln!("unprotect must be 'true' or 'false'. Got '{}'", args[3]);
-                std::process::exit(1);
+        Command::Grep { input, pattern, ignore_case, scope, tags, output } => {
+            let pattern = regex::RegexBuilder::new(&pattern)
+                .case_insensitive(ignore_case)
+                .build()
+                .context("Invalid pattern")?;
+            let options = GrepOptions { pattern, scope, tags };
+
+            match output {
+                Some(output) => grep_tmx(&input, &output, &options).map(|_| ()),
+                None => grep(&input, &mut std::io::BufWriter::new(std::io::stdout().lock()), &options).map(|_| ()),
             }
-        };
-        let input_files = &args[4..].to_vec();
+        }
 
-        return concat(input_files, output, unprotect);
-    }
+        Command::Replace { input, output, rule, rules, ignore_case, lang, changeid } => {
+            let mut all_rules = rule.chunks(2)
+                .map(|pair| Rule::new(&pair[0], &pair[1], ignore_case))
+                .collect::<Result<Vec<Rule>>>()?;
+            if let Some(path) = rules {
+                all_rules.extend(read_rules(&path, ignore_case)?);
+            }
+            if all_rules.is_empty() {
+                return Err(anyhow::anyhow!("No rules given. Use --rule or --rules"));
+            }
 
-    if "concat_dir" == command {
-        if args.len() < 5 {
-            eprintln!("Usage: {} {} <input_dir> <output.tmx> <unprotect: true|false>", args[0], command);
-            std::process::exit(1);
+            let options = ReplaceOptions {
+                rules: all_rules,
+                lang,
+                change_date: chrono::Utc::now(),
+                change_id: changeid,
+            };
+            let counts = replace(&input, &output, &options)?;
+
+            // keep the counts out of the TMX when it goes to stdout
+            for (rule, count) in options.rules.iter().zip(counts) {
+                let line = format!("{}\t{} => {}", count, rule.pattern, rule.replacement);
+                if output == STDIO {
+                    eprintln!("{}", line);
+                } else {
+                    println!("{}", line);
+                }
+            }
+            Ok(())
         }
 
-        let unprotect = match args[4].as_str() {
-            "true" => true,
-            "false" => false,
-            _ => {
-                eprintln!("unprotect must be 'true' or 'false'. Got '{}'", args[4]);
-                std::process::exit(1);
+        Command::Split { input, output, by } => {
+            for (path, tus) in split(&input, &output, &by)? {
+                println!("{}\t{}", tus, path);
             }
-        };
+            Ok(())
+        }
 
-        return concat_dir(&args[2], &args[3], unprotect);
-    }
+        Command::Sample { input, output, size, seed } => sample(&input, &output, size, seed).map(|_| ()),
 
-    if "filter" == command {
-        if args.len() != 8 {
-            eprintln!("Usage: {} {} <input.tmx> <output.tmx> <skipAuthor: true|false> <skipDocument: true|false> <skipContext: true|false>", args[0], command);
-            std::process::exit(1);
+        Command::Partition { input, output, ratio, seed, same_source, format } => {
+            let options = PartitionOptions { ratios: ratio, seed, same_source, format };
+            for (part, tus) in partition(&input, &output, &options)? {
+                println!("{}\t{}", tus, part);
+            }
+            Ok(())
         }
 
-        let skip_author = match args[4].as_str() {
-            "true" => true,
-            "false" => false,
-            _ => {
-                eprintln!("skipAuthor must be 'true' or 'false'. Got '{}'", args[4]);
-                std::process::exit(1);
+        Command::Pipeline { input, output, mut stage, stages, changeid, namespace } => {
+            if let Some(path) = stages {
+                stage.extend(read_stages(&path)?);
+            }
+            if stage.is_empty() {
+                return Err(anyhow::anyhow!("No stages given. Use --stage or --stages"));
             }
-        };
-
-        let skip_document = match args[5].as_str() {
-            "true" => true,
-            "false" => false,
-            _ => {
-                eprintln!("skipDocument must be 'true' or 'false'. Got '{}'", args[5]);
-                std::process::exit(1);
+
+            let options = PipelineOptions {
+                stages: stage,
+                change_date: chrono::Utc::now(),
+                change_id: changeid,
+                namespaces: namespaces(&namespace)?,
+            };
+            for report in pipeline(&input, &output, &options)? {
+                if output == STDIO {
+                    eprintln!("{}", report);
+                } else {
+                    println!("{}", report);
+                }
+            }
+            Ok(())
+        }
+
+        Command::T5n { input, output, mode, pattern, namespace } => {
+            let pattern = match pattern {
+                Some(_) if mode != T5nMode::Protect => return Err(anyhow::anyhow!("--pattern only applies to --mode protect")),
+                Some(pattern) => Some(regex::Regex::new(&pattern).context("Invalid pattern")?),
+                None => None,
+            };
+            let options = T5nOptions { mode, pattern, namespaces: namespaces(&namespace)? };
+            let count = t5n(&input, &output, &options)?;
+
+            let line = format!("{} placeholders", count);
+            if output == STDIO {
+                eprintln!("{}", line);
+            } else {
+                println!("{}", line);
             }
-        };
-
-        let skip_context = match args[6].as_str() {
-            "true" => true,
-            "false" => false,
-            _ => {
-                eprintln!("skipContext must be 'true' or 'false'. Got '{}'", args[6]);
-                std::process::exit(1);
+            Ok(())
+        }
+
+        Command::InspectTags { input, json, namespace } => {
+            let inspection = inspect_tags(&input, &namespaces(&namespace)?)?;
+            let mut out = std::io::BufWriter::new(std::io::stdout().lock());
+            if json {
+                inspection.write_json(&mut out)?;
+            } else {
+                inspection.write_text(&mut out)?;
             }
-        };
-
-        let keep_diff_targets = match args[7].as_str() {
-            "true" => true,
-            "false" => false,
-            _ => {
-                eprintln!("keepDiffTargets must be 'true' or 'false'. Got '{}'", args[7]);
-                std::process::exit(1);
+            out.flush()?;
+            Ok(())
+        }
+
+        Command::Validate { input, strict } => {
+            let findings = validate(&input)?;
+            let errors = findings.iter().filter(|finding| finding.severity == Severity::Error).count();
+            let warnings = findings.len() - errors;
+
+            let mut out = std::io::BufWriter::new(std::io::stdout().lock());
+            for finding in &findings {
+                writeln!(out, "{}:{}", input, finding)?;
             }
-        };
+            writeln!(out, "{} errors, {} warnings", errors, warnings)?;
+            out.flush()?;
 
-        let skip_options = filter::SkipOptions {
-            skip_author: skip_author,
-            skip_document: skip_document,
-            skip_context: skip_context,
-            keep_diff_targets: keep_diff_targets,
-        };
+            if errors > 0 || (strict && warnings > 0) {
+                return Err(anyhow::anyhow!("{} is not valid TMX", input));
+            }
+            Ok(())
+        }
 
-        return filter::filter(&args[2], &args[3], skip_options);
+        Command::Completions { shell } => {
+            clap_complete::generate(shell, &mut Cli::command(), "tmx-utils", &mut std::io::stdout());
+            Ok(())
+        }
     }
+}
 
-    eprintln!("No command specified.");
-    eprintln!("Usage:");
-    eprintln!("  {} trim <input.tmx> <output.tmx> <N>", args[0]);
-    eprintln!("  {} concat <output.tmx> <unprotect: true|false> <input1.tmx> [<input2.tmx> ...]", args[0]);
-    eprintln!("  {} filter <input.tmx> <output.tmx> <skipAuthor: true|false> <skipDocument: true|false> <skipContext: true|false> <keepDiffTargets: true|false>", args[0]);
+fn is_broken_pipe(e: &anyhow::Error) -> bool {
+    e.chain().any(|cause| {
+        cause.downcast_ref::<std::io::Error>().is_some_and(|e| e.kind() == std::io::ErrorKind::BrokenPipe)
+    })
+}
 
-    Ok(())
+fn main() -> ExitCode {
+    let cli = Cli::parse();
+
+    match run(cli.command) {
+        Ok(()) => ExitCode::SUCCESS,
+        // the next command of a pipe, e.g. `head`, stopped reading
+        Err(e) if is_broken_pipe(&e) => ExitCode::SUCCESS,
+        Err(e) => {
+            eprintln!("Error: {:#}", e);
+            ExitCode::FAILURE
+        }
+    }
 }
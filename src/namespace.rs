@@ -0,0 +1,222 @@
+use anyhow::Result;
+use quick_xml::events::{BytesStart, Event};
+use quick_xml::name::{Namespace, NamespaceResolver, PrefixDeclaration, ResolveResult};
+use std::fmt;
+use std::str::FromStr;
+
+use crate::tu::{attribute, Tu};
+
+/// How the placeholder elements of a namespace are read, e.g. `<t5:n id="1" r="eA==" n="4"/>` for t5memory.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct PlaceholderHandler {
+    /// Local name of the placeholder element, `n` for t5memory.
+    pub element: String,
+    /// Attribute holding the protected text, `n` for t5memory.
+    pub value: String,
+    /// Attribute pairing a placeholder with the one of another language, `id` for t5memory.
+    pub id: Option<String>,
+    /// Attribute holding the base64-encoded protection rule, `r` for t5memory.
+    pub rule: Option<String>,
+}
+
+impl PlaceholderHandler {
+    pub fn t5memory() -> Self {
+        PlaceholderHandler {
+            element: "n".to_string(),
+            value: "n".to_string(),
+            id: Some("id".to_string()),
+            rule: Some("r".to_string()),
+        }
+    }
+
+    /// Unescaped value of the attribute holding the protected text.
+    pub fn value_of(&self, e: &BytesStart) -> Option<String> {
+        attribute(e, &self.value)
+    }
+
+    pub fn id_of(&self, e: &BytesStart) -> Option<String> {
+        self.id.as_deref().and_then(|id| attribute(e, id))
+    }
+
+    pub fn rule_of(&self, e: &BytesStart) -> Option<String> {
+        self.rule.as_deref().and_then(|rule| attribute(e, rule))
+    }
+}
+
+/// `t5memory`, or `element=<name>;value=<attribute>[;id=<attribute>][;rule=<attribute>]`.
+impl FromStr for PlaceholderHandler {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        if s == "t5memory" {
+            return Ok(PlaceholderHandler::t5memory());
+        }
+
+        let (mut element, mut value, mut id, mut rule) = (None, None, None, None);
+        for option in s.split(';').filter(|option| !option.trim().is_empty()) {
+            match option.split_once('=') {
+                Some(("element", name)) => element = Some(name.to_string()),
+                Some(("value", name)) => value = Some(name.to_string()),
+                Some(("id", name)) => id = Some(name.to_string()),
+                Some(("rule", name)) => rule = Some(name.to_string()),
+                _ => return Err(anyhow::anyhow!(
+                    "Unknown handler option '{}'. Expected element=<name>, value=<attribute>, id=<attribute> or rule=<attribute>",
+                    option
+                )),
+            }
+        }
+
+        match (element, value) {
+            (Some(element), Some(value)) => Ok(PlaceholderHandler { element, value, id, rule }),
+            _ => Err(anyhow::anyhow!(
+                "Unknown handler '{}'. Expected t5memory or element=<name>;value=<attribute>[;id=<attribute>][;rule=<attribute>]",
+                s
+            )),
+        }
+    }
+}
+
+impl fmt::Display for PlaceholderHandler {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if *self == PlaceholderHandler::t5memory() {
+            return write!(f, "t5memory");
+        }
+        write!(f, "element={};value={}", self.element, self.value)?;
+        if let Some(id) = &self.id {
+            write!(f, ";id={}", id)?;
+        }
+        if let Some(rule) = &self.rule {
+            write!(f, ";rule={}", rule)?;
+        }
+        Ok(())
+    }
+}
+
+/// Which inline elements are placeholders, matched by namespace URI rather than by prefix.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Namespaces {
+    /// Handlers of namespace URIs.
+    pub uris: Vec<(String, PlaceholderHandler)>,
+    /// Handlers of conventional prefixes, for files that use a prefix without declaring it, like t5memory exports.
+    /// The URI such a prefix is bound to where a TU starts, e.g. by `xmlns:t5` on `<tmx>`, gets the same handler;
+    /// a prefix bound inside the TU is only matched by its URI.
+    pub prefixes: Vec<(String, PlaceholderHandler)>,
+}
+
+impl Default for Namespaces {
+    fn default() -> Self {
+        Namespaces {
+            uris: Vec::new(),
+            prefixes: vec![("t5".to_string(), PlaceholderHandler::t5memory())],
+        }
+    }
+}
+
+impl Namespaces {
+    /// The placeholder handler of every event of `tu`, in event order; `None` for events that are not the start of
+    /// a placeholder element.
+    pub fn placeholders(&self, tu: &Tu) -> Vec<Option<&PlaceholderHandler>> {
+        let mut resolver = NamespaceResolver::default();
+        for (prefix, uri) in &tu.namespaces {
+            let prefix = match prefix.as_str() {
+                "" => PrefixDeclaration::Default,
+                prefix => PrefixDeclaration::Named(prefix.as_bytes()),
+            };
+            // the reader only reports valid bindings
+            let _ = resolver.add(prefix, Namespace(uri.as_bytes()));
+        }
+
+        // URIs bound to a conventional prefix outside the TU's content, whatever prefix the content uses for them
+        let conventional: Vec<(&str, &PlaceholderHandler)> = tu.namespaces.iter()
+            .filter_map(|(prefix, uri)| Some((uri.as_str(), self.prefix_handler(prefix.as_bytes())?)))
+            .collect();
+
+        tu.events.iter().enumerate().map(|(i, ev)| match ev {
+            // the bindings of <tu> itself are in `tu.namespaces`
+            _ if i == 0 => None,
+            Event::Start(e) => {
+                let _ = resolver.push(e);
+                self.handler(&resolver, &conventional, e)
+            }
+            Event::Empty(e) => {
+                let _ = resolver.push(e);
+                let handler = self.handler(&resolver, &conventional, e);
+                resolver.pop();
+                handler
+            }
+            Event::End(_) => {
+                resolver.pop();
+                None
+            }
+            _ => None,
+        }).collect()
+    }
+
+    fn handler<'a>(
+        &'a self,
+        resolver: &NamespaceResolver,
+        conventional: &[(&str, &'a PlaceholderHandler)],
+        e: &BytesStart,
+    ) -> Option<&'a PlaceholderHandler> {
+        let (namespace, local_name) = resolver.resolve_element(e.name());
+        let handler = match namespace {
+            ResolveResult::Bound(uri) => self.uris.iter()
+                .map(|(known, handler)| (known.as_str(), handler))
+                .chain(conventional.iter().copied())
+                .find(|(known, _)| known.as_bytes() == uri.as_ref())
+                .map(|(_, handler)| handler),
+            // an undeclared prefix
+            ResolveResult::Unknown(prefix) => self.prefix_handler(&prefix),
+            ResolveResult::Unbound => None,
+        }?;
+        (local_name.as_ref() == handler.element.as_bytes()).then_some(handler)
+    }
+
+    fn prefix_handler(&self, prefix: &[u8]) -> Option<&PlaceholderHandler> {
+        self.prefixes.iter().find(|(known, _)| known.as_bytes() == prefix).map(|(_, handler)| handler)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::reader::TmxReader;
+    use pretty_assertions::assert_eq;
+
+    #[test]
+    fn test_placeholders() {
+        let tmx = r#"<tmx xmlns:t5="urn:t5" xmlns:mq="urn:mq"><body>
+            <tu><tuv xml:lang="de"><seg><t5:n id="1" n="4"/> <tm:n xmlns:tm="urn:t5" id="2" n="5"/> <x:n xmlns:x="urn:x"/></seg></tuv>
+            <tuv xml:lang="en"><seg><mq:rxt val="{0}"/> <mq:n/></seg></tuv></tu>
+            <tu><tuv xml:lang="de"><seg><t5:n xmlns:t5="urn:mq" n="6"/> <t5:n xmlns:t5="urn:other" n="7"/></seg></tuv></tu>
+        </body></tmx>"#;
+        let tus: Vec<Tu> = TmxReader::new(tmx.as_bytes()).tus().collect::<Result<_>>().unwrap();
+
+        let mut namespaces = Namespaces::default();
+        namespaces.uris.push(("urn:t5".to_string(), PlaceholderHandler::t5memory()));
+        namespaces.uris.push(("urn:mq".to_string(), "element=rxt;value=val".parse().unwrap()));
+
+        let values = |namespaces: &Namespaces, tu: &Tu| -> Vec<String> {
+            tu.events.iter().zip(namespaces.placeholders(tu))
+                .filter_map(|(ev, handler)| match (ev, handler) {
+                    (Event::Empty(e), Some(handler)) => handler.value_of(e),
+                    _ => None,
+                })
+                .collect()
+        };
+        assert_eq!(values(&namespaces, &tus[0]), vec!["4", "5", "{0}"]);
+        // a prefix bound inside the TU is matched by its URI only, whatever its name
+        assert_eq!(values(&namespaces, &tus[1]), Vec::<String>::new());
+        // the URI of xmlns:t5 on <tmx> is t5memory's, also under another prefix
+        assert_eq!(values(&Namespaces::default(), &tus[0]), vec!["4", "5"]);
+        assert_eq!(values(&Namespaces::default(), &tus[1]), Vec::<String>::new());
+
+        let undeclared: Vec<Tu> = TmxReader::new(r#"<tu><seg><t5:n n="8"/></seg></tu>"#.as_bytes()).tus().collect::<Result<_>>().unwrap();
+        assert_eq!(values(&namespaces, &undeclared[0]), vec!["8"]);
+
+        for handler in ["t5memory", "element=rxt;value=val", "element=ph;value=n;id=x;rule=r"] {
+            assert_eq!(handler.parse::<PlaceholderHandler>().unwrap().to_string(), handler);
+        }
+        assert!("element=rxt".parse::<PlaceholderHandler>().is_err());
+    }
+}
@@ -0,0 +1,515 @@
+use anyhow::{Context, Result};
+use chrono::{DateTime, Utc};
+use quick_xml::events::{BytesStart, Event};
+use std::fmt;
+use std::str::FromStr;
+
+use crate::date::tu_label;
+use crate::filter::{Duplicates, Strategy};
+use crate::io::{create_output, open_input};
+use crate::key::KeySpec;
+use crate::namespace::Namespaces;
+use crate::reader::{TmxItem, TmxReader};
+use crate::replace::{ReplaceOptions, Rule};
+use crate::select::Predicate;
+use crate::t5n::{convert, T5nMode};
+use crate::tu::{attribute, Tu};
+use crate::validate::{validate_tu, Severity};
+use crate::whitespace::is_xml_whitespace;
+use crate::writer::TmxWriter;
+
+/// What the `validate` stage does with an invalid TU.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum OnInvalid {
+    /// Drop it with a warning.
+    #[default]
+    Drop,
+    /// Stop the pipeline with an error.
+    Fail,
+}
+
+/// One stage of a pipeline, written like `skip:100` or `dedupe:keep=oldest;key=source,target`.
+#[derive(Clone, Debug)]
+pub enum StageSpec {
+    /// `skip:N`: drop the first N TUs.
+    Skip(usize),
+    /// `take:N`: pass the first N TUs, drop the rest.
+    Take(usize),
+    /// `select:<condition>`: keep the TUs matching a `select` condition.
+    Select(Predicate),
+    /// `reject:<condition>`: drop the TUs matching a `select` condition.
+    Reject(Predicate),
+    /// `dedupe[:keep=<strategy>;key=<fields>]`: keep one TU of each group of duplicates, like `filter`.
+    /// The survivors are passed on in input order once every TU has been read.
+    Dedupe { key: KeySpec, strategy: Strategy },
+    /// `replace:<pattern> => <replacement>`: a `replace` rule.
+    Replace(Rule),
+    /// `unprotect`: replace `<t5:n>` placeholders with their `n` value.
+    Unprotect,
+    /// `t5n:<n|r|ph|protect>`: convert `<t5:n>` placeholders like the `t5n` command.
+    T5n(T5nMode),
+    /// `validate[:drop|fail]`: check a TU against the rules of the `validate` command.
+    Validate(OnInvalid),
+}
+
+impl FromStr for StageSpec {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let (name, arg) = match s.split_once(':') {
+            Some((name, arg)) => (name, Some(arg)),
+            None => (s, None),
+        };
+        let count = |arg: Option<&str>| -> Result<usize> {
+            let arg = arg.ok_or_else(|| anyhow::anyhow!("Expected {}:<count>", name))?;
+            arg.trim().parse().context(format!("Invalid count '{}' in stage {}", arg, s))
+        };
+
+        match (name, arg) {
+            ("skip", _) => Ok(StageSpec::Skip(count(arg)?)),
+            ("take", _) => Ok(StageSpec::Take(count(arg)?)),
+            ("select", Some(condition)) => Ok(StageSpec::Select(condition.parse()?)),
+            ("reject", Some(condition)) => Ok(StageSpec::Reject(condition.parse()?)),
+            ("dedupe", None) => Ok(StageSpec::Dedupe { key: KeySpec::default(), strategy: Strategy::default() }),
+            ("dedupe", Some(options)) => {
+                let (mut key, mut strategy) = (KeySpec::default(), Strategy::default());
+                for option in options.split(';').filter(|option| !option.trim().is_empty()) {
+                    match option.split_once('=') {
+                        Some(("key", fields)) => key = fields.parse()?,
+                        Some(("keep", keep)) => strategy = keep.parse()?,
+                        _ => return Err(anyhow::anyhow!("Unknown dedupe option '{}'. Expected keep=<strategy> or key=<fields>", option)),
+                    }
+                }
+                Ok(StageSpec::Dedupe { key, strategy })
+            }
+            ("replace", Some(rule)) => match rule.split_once(" => ") {
+                Some((pattern, replacement)) => Ok(StageSpec::Replace(Rule::new(pattern, replacement, false)?)),
+                None => Err(anyhow::anyhow!("Expected replace:<pattern> => <replacement>, got '{}'", s)),
+            },
+            ("unprotect", None) => Ok(StageSpec::Unprotect),
+            ("t5n", Some(mode)) => Ok(StageSpec::T5n(mode.parse()?)),
+            ("validate", None | Some("drop")) => Ok(StageSpec::Validate(OnInvalid::Drop)),
+            ("validate", Some("fail")) => Ok(StageSpec::Validate(OnInvalid::Fail)),
+            ("select" | "reject", None) => Err(anyhow::anyhow!("Expected {}:<condition>", name)),
+            ("replace", None) => Err(anyhow::anyhow!("Expected replace:<pattern> => <replacement>")),
+            ("t5n", None) => Err(anyhow::anyhow!("Expected t5n:<n|r|ph|protect>")),
+            _ => Err(anyhow::anyhow!(
+                "Unknown stage '{}'. Expected skip:N, take:N, select:<condition>, reject:<condition>, dedupe, replace:<pattern> => <replacement>, unprotect, t5n:<mode> or validate",
+                s
+            )),
+        }
+    }
+}
+
+impl fmt::Display for StageSpec {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            StageSpec::Skip(n) => write!(f, "skip:{}", n),
+            StageSpec::Take(n) => write!(f, "take:{}", n),
+            StageSpec::Select(predicate) => write!(f, "select:{}", predicate),
+            StageSpec::Reject(predicate) => write!(f, "reject:{}", predicate),
+            StageSpec::Dedupe { key, strategy } => write!(f, "dedupe:keep={};key={}", strategy, key),
+            StageSpec::Replace(rule) => write!(f, "replace:{} => {}", rule.pattern, rule.replacement),
+            StageSpec::Unprotect => write!(f, "unprotect"),
+            StageSpec::T5n(mode) => write!(f, "t5n:{}", mode),
+            StageSpec::Validate(OnInvalid::Drop) => write!(f, "validate"),
+            StageSpec::Validate(OnInvalid::Fail) => write!(f, "validate:fail"),
+        }
+    }
+}
+
+/// Reads stages from a file with one stage per line, written like `--stage`, so that a pipeline moves between the
+/// command line and a file as it is. Empty lines and lines starting with `#` are skipped.
+pub fn read_stages(path: &str) -> Result<Vec<StageSpec>> {
+    let content = std::fs::read_to_string(path).context(format!("Cannot read stages file {}", path))?;
+
+    content.lines()
+        .enumerate()
+        .filter(|(_, line)| !line.trim().is_empty() && !line.trim_start().starts_with('#'))
+        .map(|(i, line)| line.trim().parse().context(format!("{}:{}", path, i + 1)))
+        .collect()
+}
+
+pub struct PipelineOptions {
+    pub stages: Vec<StageSpec>,
+    /// Written to `changedate` and `changeid` of every TU changed by a `replace` stage.
+    pub change_date: DateTime<Utc>,
+    pub change_id: String,
+    /// Which inline elements are placeholders for the `unprotect` and `t5n` stages.
+    pub namespaces: Namespaces,
+}
+
+/// A TU-level operation of a pipeline.
+trait Stage {
+    /// Returns the TU to pass on to the next stage, or `None` to drop it.
+    fn process(&mut self, tu: Tu) -> Result<Option<Tu>>;
+
+    /// Called with the `<header>` of the input, before the first TU.
+    fn header(&mut self, _header: &BytesStart) {}
+
+    /// Called once every TU has been processed; returns the TUs held back until then.
+    fn finish(&mut self) -> Vec<Tu> {
+        Vec::new()
+    }
+
+    /// Counters besides the TUs in and out, e.g. `("duplicates", 3)`.
+    fn counters(&self) -> Vec<(&'static str, usize)> {
+        Vec::new()
+    }
+}
+
+struct Skip {
+    remaining: usize,
+}
+
+impl Stage for Skip {
+    fn process(&mut self, tu: Tu) -> Result<Option<Tu>> {
+        if self.remaining > 0 {
+            self.remaining -= 1;
+            return Ok(None);
+        }
+        Ok(Some(tu))
+    }
+}
+
+struct Take {
+    remaining: usize,
+}
+
+impl Stage for Take {
+    fn process(&mut self, tu: Tu) -> Result<Option<Tu>> {
+        if self.remaining == 0 {
+            return Ok(None);
+        }
+        self.remaining -= 1;
+        Ok(Some(tu))
+    }
+}
+
+struct Select {
+    predicate: Predicate,
+    keep: bool,
+}
+
+impl Stage for Select {
+    fn process(&mut self, tu: Tu) -> Result<Option<Tu>> {
+        Ok((self.predicate.matches(&tu) == self.keep).then_some(tu))
+    }
+}
+
+struct Dedupe {
+    duplicates: Duplicates,
+}
+
+impl Stage for Dedupe {
+    fn process(&mut self, tu: Tu) -> Result<Option<Tu>> {
+        self.duplicates.add(tu);
+        Ok(None)
+    }
+
+    fn finish(&mut self) -> Vec<Tu> {
+        self.duplicates.take_tus()
+    }
+
+    fn counters(&self) -> Vec<(&'static str, usize)> {
+        vec![("duplicates", self.duplicates.dropped())]
+    }
+}
+
+struct Replace {
+    options: ReplaceOptions,
+    changed: usize,
+}
+
+impl Stage for Replace {
+    fn process(&mut self, mut tu: Tu) -> Result<Option<Tu>> {
+        if self.options.apply(&mut tu).contains(&true) {
+            self.changed += 1;
+        }
+        Ok(Some(tu))
+    }
+
+    fn counters(&self) -> Vec<(&'static str, usize)> {
+        vec![("changed", self.changed)]
+    }
+}
+
+struct T5n {
+    mode: T5nMode,
+    namespaces: Namespaces,
+    placeholders: usize,
+}
+
+impl Stage for T5n {
+    fn process(&mut self, mut tu: Tu) -> Result<Option<Tu>> {
+        self.placeholders += convert(&mut tu, self.mode, &self.namespaces);
+        Ok(Some(tu))
+    }
+
+    fn counters(&self) -> Vec<(&'static str, usize)> {
+        vec![("placeholders", self.placeholders)]
+    }
+}
+
+struct Validate {
+    on_invalid: OnInvalid,
+    srclang: Option<String>,
+    invalid: usize,
+}
+
+impl Stage for Validate {
+    fn header(&mut self, header: &BytesStart) {
+        self.srclang = attribute(header, "srclang");
+    }
+
+    // warnings let the TU pass
+    fn process(&mut self, tu: Tu) -> Result<Option<Tu>> {
+        let problem = validate_tu(&tu, self.srclang.as_deref())
+            .into_iter()
+            .find(|(severity, _)| *severity == Severity::Error)
+            .map(|(_, message)| message);
+
+        match (problem, self.on_invalid) {
+            (None, _) => Ok(Some(tu)),
+            (Some(problem), OnInvalid::Fail) => Err(anyhow::anyhow!("{}: {}", tu_label(&tu), problem)),
+            (Some(problem), OnInvalid::Drop) => {
+                eprintln!("Warning: {}: dropped, {}", tu_label(&tu), problem);
+                self.invalid += 1;
+                Ok(None)
+            }
+        }
+    }
+
+    fn counters(&self) -> Vec<(&'static str, usize)> {
+        vec![("invalid", self.invalid)]
+    }
+}
+
+impl StageSpec {
+    fn build(&self, options: &PipelineOptions) -> Box<dyn Stage> {
+        match self {
+            StageSpec::Skip(n) => Box::new(Skip { remaining: *n }),
+            StageSpec::Take(n) => Box::new(Take { remaining: *n }),
+            StageSpec::Select(predicate) => Box::new(Select { predicate: predicate.clone(), keep: true }),
+            StageSpec::Reject(predicate) => Box::new(Select { predicate: predicate.clone(), keep: false }),
+            StageSpec::Dedupe { key, strategy } => Box::new(Dedupe { duplicates: Duplicates::new(key.clone(), strategy.clone()) }),
+            StageSpec::Replace(rule) => Box::new(Replace {
+                options: ReplaceOptions {
+                    rules: vec![rule.clone()],
+                    lang: None,
+                    change_date: options.change_date,
+                    change_id: options.change_id.clone(),
+                },
+                changed: 0,
+            }),
+            StageSpec::Unprotect => Box::new(T5n { mode: T5nMode::N, namespaces: options.namespaces.clone(), placeholders: 0 }),
+            StageSpec::T5n(mode) => Box::new(T5n { mode: *mode, namespaces: options.namespaces.clone(), placeholders: 0 }),
+            StageSpec::Validate(on_invalid) => Box::new(Validate { on_invalid: *on_invalid, srclang: None, invalid: 0 }),
+        }
+    }
+}
+
+/// The counters of one stage after a run.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct StageReport {
+    pub stage: String,
+    pub input: usize,
+    pub output: usize,
+    pub counters: Vec<(&'static str, usize)>,
+}
+
+impl fmt::Display for StageReport {
+    /// `dedupe:keep=newest;key=source\t6 in, 3 out, 3 duplicates`
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}\t{} in, {} out", self.stage, self.input, self.output)?;
+        for (name, count) in &self.counters {
+            write!(f, ", {} {}", count, name)?;
+        }
+        Ok(())
+    }
+}
+
+struct Stages {
+    stages: Vec<Box<dyn Stage>>,
+    counts: Vec<(usize, usize)>,
+}
+
+impl Stages {
+    fn header(&mut self, header: &BytesStart) {
+        for stage in &mut self.stages {
+            stage.header(header);
+        }
+    }
+
+    /// Passes `tu` through the stages from `from` on; `None` when one of them dropped it.
+    fn run(&mut self, from: usize, mut tu: Tu) -> Result<Option<Tu>> {
+        for (stage, (input, output)) in self.stages.iter_mut().zip(self.counts.iter_mut()).skip(from) {
+            *input += 1;
+            match stage.process(tu)? {
+                Some(next) => {
+                    *output += 1;
+                    tu = next;
+                }
+                None => return Ok(None),
+            }
+        }
+        Ok(Some(tu))
+    }
+
+    /// Flushes the TUs held back by each stage through the stages after it.
+    fn finish(&mut self) -> Result<Vec<Tu>> {
+        let mut tus = Vec::new();
+        for i in 0..self.stages.len() {
+            for tu in self.stages[i].finish() {
+                self.counts[i].1 += 1;
+                tus.extend(self.run(i + 1, tu)?);
+            }
+        }
+        Ok(tus)
+    }
+}
+
+/// Runs every TU of `input` through the stages, in one pass, and writes the TUs that come out.
+/// Everything outside of TUs is copied. Returns the counters of each stage.
+pub fn pipeline(input: &str, output: &str, options: &PipelineOptions) -> Result<Vec<StageReport>> {
+    let mut reader = TmxReader::new(open_input(input).context("Cannot open input file")?);
+
+    let mut writer = TmxWriter::new(create_output(output).context("Cannot create output file")?);
+
+    let mut stages = Stages {
+        stages: options.stages.iter().map(|spec| spec.build(options)).collect(),
+        counts: vec![(0, 0); options.stages.len()],
+    };
+
+    // whitespace is held back until the next item, and dropped along with a dropped TU
+    let mut pending: Option<Event<'static>> = None;
+    // the whitespace in front of the last TU, used for TUs held back until the end
+    let mut indent: Option<Event<'static>> = None;
+
+    while let Some(item) = reader.read_item()? {
+        match item {
+            TmxItem::Event(Event::Text(t)) if is_xml_whitespace(t.as_ref()) => {
+                if let Some(ws) = pending.replace(Event::Text(t)) {
+                    writer.write_event(ws)?;
+                }
+            }
+            TmxItem::Tu(tu) => {
+                let ws = pending.take();
+                if ws.is_some() {
+                    indent = ws.clone();
+                }
+                if let Some(tu) = stages.run(0, tu)? {
+                    if let Some(ws) = ws {
+                        writer.write_event(ws)?;
+                    }
+                    writer.write_tu(&tu)?;
+                }
+            }
+            TmxItem::Event(ev) => {
+                if let Event::Start(e) | Event::Empty(e) = &ev
+                    && e.name().as_ref() == b"header"
+                {
+                    stages.header(e);
+                }
+                if matches!(ev, Event::End(ref e) if e.name().as_ref() == b"body") {
+                    for tu in stages.finish()? {
+                        if let Some(indent) = &indent {
+                            writer.write_event(indent.borrow())?;
+                        }
+                        writer.write_tu(&tu)?;
+                    }
+                }
+                if let Some(ws) = pending.take() {
+                    writer.write_event(ws)?;
+                }
+                writer.write_event(ev)?;
+            }
+        }
+    }
+    if let Some(ws) = pending.take() {
+        writer.write_event(ws)?;
+    }
+
+    writer.into_inner().finish()?;
+
+    Ok(options.stages.iter()
+        .zip(stages.stages.iter().zip(stages.counts))
+        .map(|(spec, (stage, (input, output)))| StageReport {
+            stage: spec.to_string(),
+            input,
+            output,
+            counters: stage.counters(),
+        })
+        .collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pretty_assertions::assert_eq;
+
+    #[test]
+    fn test_parse_stage() {
+        for s in [
+            "skip:2",
+            "take:10",
+            "select:author:manager",
+            "reject:docname:docname2",
+            "dedupe:keep=oldest;key=source,prop:tmgr:docname",
+            "replace:nice (\\d) => great $1",
+            "unprotect",
+            "t5n:ph",
+            "validate",
+            "validate:fail",
+        ] {
+            assert_eq!(s, s.parse::<StageSpec>().unwrap().to_string());
+        }
+
+        assert_eq!(
+            "dedupe:keep=newest;key=source,creationid,prop:tmgr:docname,prop:tmgr:context",
+            "dedupe".parse::<StageSpec>().unwrap().to_string()
+        );
+        assert!("skip".parse::<StageSpec>().is_err());
+        assert!("replace:nice".parse::<StageSpec>().is_err());
+        assert!("dedupe:order=input".parse::<StageSpec>().is_err());
+        assert!("sort".parse::<StageSpec>().is_err());
+    }
+
+    #[test]
+    fn test_read_stages() {
+        let stages = read_stages("test-data/pipeline/stages.txt").unwrap();
+        assert_eq!(
+            vec!["skip:100", "dedupe:keep=oldest;key=source,target", "replace:colour => color", "validate:fail"],
+            stages.iter().map(|stage| stage.to_string()).collect::<Vec<_>>()
+        );
+    }
+
+    #[test]
+    fn test_pipeline() {
+        let stages = ["skip:1", "reject:tuid:3", "dedupe:keep=oldest;key=source", "unprotect", "replace:nice => great", "validate"];
+        let options = PipelineOptions {
+            stages: stages.iter().map(|s| s.parse().unwrap()).collect(),
+            change_date: DateTime::from_timestamp(1_700_000_000, 0).unwrap(),
+            change_id: "tester".to_string(),
+            namespaces: Namespaces::default(),
+        };
+        let reports = pipeline("test-data/pipeline/test.tmx", "test-data/pipeline/output.tmx", &options).unwrap();
+
+        let expected = std::fs::read_to_string("test-data/pipeline/expected.tmx").unwrap();
+        let output = std::fs::read_to_string("test-data/pipeline/output.tmx").unwrap();
+        std::fs::remove_file("test-data/pipeline/output.tmx").unwrap();
+
+        assert_eq!(expected, output);
+        assert_eq!(
+            vec![
+                "skip:1\t7 in, 6 out",
+                "reject:tuid:3\t6 in, 5 out",
+                "dedupe:keep=oldest;key=source\t5 in, 2 out, 3 duplicates",
+                "unprotect\t2 in, 2 out, 1 placeholders",
+                "replace:nice => great\t2 in, 2 out, 1 changed",
+                "validate\t2 in, 1 out, 1 invalid",
+            ],
+            reports.iter().map(|report| report.to_string()).collect::<Vec<_>>()
+        );
+    }
+}
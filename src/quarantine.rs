@@ -0,0 +1,81 @@
+use anyhow::{Context, Result};
+use std::cell::RefCell;
+use std::fmt;
+use std::io::Write;
+use std::rc::Rc;
+
+use crate::io::{create_output, Output};
+
+/// The file where readers that recover from malformed TUs put them. Clones share the file, so the readers of
+/// several inputs can write to the same one.
+///
+/// Every TU is written as it was in the input, after a comment with its input, byte offset and error:
+/// `<!-- big.tmx, byte 1234: ... -->`. Call [`Quarantine::finish`] when done.
+#[derive(Clone)]
+pub struct Quarantine {
+    inner: Rc<RefCell<QuarantineFile>>,
+}
+
+struct QuarantineFile {
+    path: String,
+    output: Option<Output>,
+    count: usize,
+}
+
+impl Quarantine {
+    /// Creates `path`, compressed according to its extension, even when nothing ends up in it.
+    pub fn create(path: &str) -> Result<Self> {
+        let output = create_output(path).context("Cannot create quarantine file")?;
+        Ok(Quarantine {
+            inner: Rc::new(RefCell::new(QuarantineFile { path: path.to_string(), output: Some(output), count: 0 })),
+        })
+    }
+
+    pub fn path(&self) -> String {
+        self.inner.borrow().path.clone()
+    }
+
+    /// Number of TUs quarantined so far.
+    pub fn count(&self) -> usize {
+        self.inner.borrow().count
+    }
+
+    /// Writes `bytes`, found at `offset` of `input`, without the whitespace after them.
+    pub(crate) fn add(&self, input: &str, offset: u64, error: &anyhow::Error, bytes: &[u8]) -> Result<()> {
+        let mut file = self.inner.borrow_mut();
+        let output = file.output.as_mut().ok_or_else(|| anyhow::anyhow!("Quarantine file already finished"))?;
+
+        // `--` cannot appear in a comment
+        let comment = format!("{}, byte {}: {:#}", input, offset, error).replace("--", "- -");
+        writeln!(output, "<!-- {} -->", comment)?;
+        output.write_all(bytes.trim_ascii_end())?;
+        writeln!(output)?;
+
+        file.count += 1;
+        Ok(())
+    }
+
+    /// Completes the file and returns the number of TUs in it.
+    pub fn finish(self) -> Result<usize> {
+        let mut file = self.inner.borrow_mut();
+        if let Some(output) = file.output.take() {
+            output.finish().context("Cannot write quarantine file")?;
+        }
+        Ok(file.count)
+    }
+}
+
+impl fmt::Debug for Quarantine {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Quarantine").field("path", &self.inner.borrow().path).finish()
+    }
+}
+
+/// Two handles are equal when they write to the same file.
+impl PartialEq for Quarantine {
+    fn eq(&self, other: &Self) -> bool {
+        Rc::ptr_eq(&self.inner, &other.inner)
+    }
+}
+
+impl Eq for Quarantine {}
@@ -0,0 +1,461 @@
+use anyhow::Result;
+use quick_xml::events::{BytesStart, Event};
+use quick_xml::name::PrefixDeclaration;
+use quick_xml::reader::NsReader;
+use std::io::{self, BufRead, Read};
+
+use crate::quarantine::Quarantine;
+use crate::tu::Tu;
+
+/// An item of a TMX document: either a whole translation unit or any event outside of one.
+#[derive(Clone, Debug)]
+pub enum TmxItem {
+    Event(Event<'static>),
+    Tu(Tu),
+}
+
+/// Streaming TMX reader yielding one [`TmxItem`] at a time.
+///
+/// Whitespace is kept as-is so that writing every item back reproduces the input.
+/// Every TU carries the namespace bindings in scope at its `<tu>` element.
+pub struct TmxReader<R: BufRead> {
+    reader: NsReader<Source<R>>,
+    buf: Vec<u8>,
+    // the elements open outside of TUs, e.g. <tmx> and <body>
+    open: Vec<BytesStart<'static>>,
+    // input offset where the input of the current parser starts, after the start tags replayed to it
+    origin: u64,
+    replayed: u64,
+    quarantine: Option<(Quarantine, String)>,
+}
+
+impl<R: BufRead> TmxReader<R> {
+    pub fn new(inner: R) -> Self {
+        TmxReader {
+            reader: parser(Source::new(inner)),
+            buf: Vec::new(),
+            open: Vec::new(),
+            origin: 0,
+            replayed: 0,
+            quarantine: None,
+        }
+    }
+
+    /// With a quarantine, a malformed TU in `<body>` does not fail the reader: its bytes, up to the next `<tu>` or
+    /// `</body>`, go to the quarantine with their offset and the error, and reading goes on from there. `input`
+    /// names the input in the quarantine. Call before reading anything.
+    pub fn with_quarantine(mut self, quarantine: Option<&Quarantine>, input: &str) -> Self {
+        if let Some(quarantine) = quarantine {
+            self.reader.get_mut().record();
+            self.quarantine = Some((quarantine.clone(), input.to_string()));
+        }
+        self
+    }
+
+    /// Reads the next item, `None` at the end of the document.
+    pub fn read_item(&mut self) -> Result<Option<TmxItem>> {
+        loop {
+            let start = self.input_offset();
+            if self.quarantine.is_none() {
+                return self.read_item_at(start);
+            }
+
+            self.reader.get_mut().forget_before(start);
+            match self.read_item_at(start) {
+                Err(e) if self.open.last().is_some_and(|open| open.name().as_ref() == b"body") => self.recover(start, e)?,
+                result => return result,
+            }
+        }
+    }
+
+    fn read_item_at(&mut self, start: u64) -> Result<Option<TmxItem>> {
+        let ev = self.read_event()?;
+
+        match ev {
+            Event::Eof => Ok(None),
+            Event::Start(ref e) if e.name().as_ref() == b"tu" => {
+                let namespaces = self.namespaces();
+                let mut events = vec![ev];
+                loop {
+                    let ev = self.read_event()?;
+                    match ev {
+                        Event::Eof => return Err(anyhow::anyhow!("Malformed TMX: <tu> not closed")),
+                        Event::Start(ref e) if e.name().as_ref() == b"tu" => {
+                            return Err(anyhow::anyhow!("Malformed TMX: <tu> not closed before the next <tu>"));
+                        }
+                        Event::End(ref e) if e.name().as_ref() == b"tu" => {
+                            events.push(ev);
+                            break;
+                        }
+                        _ => events.push(ev),
+                    }
+                }
+                Ok(Some(TmxItem::Tu(self.tu(events, start, namespaces))))
+            }
+            Event::Empty(ref e) if e.name().as_ref() == b"tu" => {
+                let namespaces = self.namespaces();
+                Ok(Some(TmxItem::Tu(self.tu(vec![ev], start, namespaces))))
+            }
+            Event::Start(ref e) => {
+                self.open.push(e.clone());
+                Ok(Some(TmxItem::Event(ev)))
+            }
+            Event::End(_) => {
+                self.open.pop();
+                Ok(Some(TmxItem::Event(ev)))
+            }
+            _ => Ok(Some(TmxItem::Event(ev))),
+        }
+    }
+
+    // quarantines what failed at `start`, up to the next <tu> or </body>, and goes on from there with a new parser,
+    // as a parser stops at its first error
+    fn recover(&mut self, start: u64, error: anyhow::Error) -> Result<()> {
+        let source = self.reader.get_mut();
+        // the next attempt has to start further
+        if source.offset <= start {
+            return Err(error);
+        }
+        source.unread_from(start + 1);
+        let found = source.skip_to_resync_point()?;
+        if let Some((quarantine, input)) = &self.quarantine {
+            quarantine.add(input, start, &error, source.recorded_from(start))?;
+        }
+
+        // the new parser reads the start tags of the open elements first, so that it knows their namespaces and
+        // checks their end tags
+        let mut replay = Vec::new();
+        for e in &self.open {
+            replay.extend_from_slice(format!("<{}>", String::from_utf8_lossy(e)).as_bytes());
+        }
+        let replayed = replay.len() as u64;
+        if !found {
+            // the input ends inside <body>
+            for e in self.open.iter().rev() {
+                replay.extend_from_slice(format!("</{}>", String::from_utf8_lossy(e.name().as_ref())).as_bytes());
+            }
+        }
+
+        let mut source = std::mem::take(self.reader.get_mut());
+        self.origin = source.offset;
+        self.replayed = replayed;
+        source.replay(replay);
+        self.reader = parser(source);
+        for _ in 0..self.open.len() {
+            self.read_event()?;
+        }
+        Ok(())
+    }
+
+    /// Iterates over translation units only, dropping every other event.
+    pub fn tus(self) -> impl Iterator<Item = Result<Tu>> {
+        self.filter_map(|item| match item {
+            Ok(TmxItem::Tu(tu)) => Some(Ok(tu)),
+            Ok(TmxItem::Event(_)) => None,
+            Err(e) => Some(Err(e)),
+        })
+    }
+
+    // input offset of the next event
+    fn input_offset(&self) -> u64 {
+        self.origin + self.reader.buffer_position() - self.replayed
+    }
+
+    fn tu(&self, events: Vec<Event<'static>>, start: u64, namespaces: Vec<(String, String)>) -> Tu {
+        let mut tu = Tu::from_events(events);
+        tu.span = start..self.input_offset();
+        tu.namespaces = namespaces;
+        tu
+    }
+
+    // the bindings in scope at the element just read
+    fn namespaces(&self) -> Vec<(String, String)> {
+        self.reader.resolver().bindings()
+            .map(|(prefix, namespace)| {
+                let prefix = match prefix {
+                    PrefixDeclaration::Default => String::new(),
+                    PrefixDeclaration::Named(prefix) => String::from_utf8_lossy(prefix).into_owned(),
+                };
+                (prefix, String::from_utf8_lossy(namespace.as_ref()).into_owned())
+            })
+            .collect()
+    }
+
+    fn read_event(&mut self) -> Result<Event<'static>> {
+        self.buf.clear();
+        match self.reader.read_event_into(&mut self.buf) {
+            Ok(ev) => Ok(ev.into_owned()),
+            Err(e) => Err(anyhow::anyhow!(
+                "XML parse error at {:?}: {}",
+                (self.origin + self.reader.error_position()).saturating_sub(self.replayed),
+                e
+            )),
+        }
+    }
+}
+
+fn parser<R: BufRead>(source: Source<R>) -> NsReader<Source<R>> {
+    let mut reader = NsReader::from_reader(source);
+    reader.config_mut().trim_text(false);
+    reader
+}
+
+// The input of the parser. It can keep what the parser consumed, so that a malformed TU can be quarantined as it
+// was, put input back, and replay start tags to a new parser.
+struct Source<R> {
+    inner: Option<R>,
+    // read before everything else, not part of the input
+    replay: Vec<u8>,
+    replay_pos: usize,
+    // input put back, read before `inner`
+    unread: Vec<u8>,
+    unread_pos: usize,
+    // input offset of the next byte
+    offset: u64,
+    // the input from offset `record_start` on, when recording
+    record: Option<Vec<u8>>,
+    record_start: u64,
+}
+
+impl<R> Default for Source<R> {
+    fn default() -> Self {
+        Source {
+            inner: None,
+            replay: Vec::new(),
+            replay_pos: 0,
+            unread: Vec::new(),
+            unread_pos: 0,
+            offset: 0,
+            record: None,
+            record_start: 0,
+        }
+    }
+}
+
+impl<R: BufRead> Source<R> {
+    fn new(inner: R) -> Self {
+        Source { inner: Some(inner), ..Source::default() }
+    }
+
+    fn record(&mut self) {
+        self.record = Some(Vec::new());
+        self.record_start = self.offset;
+    }
+
+    fn forget_before(&mut self, offset: u64) {
+        if let Some(record) = &mut self.record {
+            let len = (offset.saturating_sub(self.record_start) as usize).min(record.len());
+            record.drain(..len);
+            self.record_start += len as u64;
+        }
+    }
+
+    fn recorded_from(&self, offset: u64) -> &[u8] {
+        let record = self.record.as_deref().unwrap_or_default();
+        &record[(offset.saturating_sub(self.record_start) as usize).min(record.len())..]
+    }
+
+    // puts back the recorded input from `offset` on
+    fn unread_from(&mut self, offset: u64) {
+        let Some(record) = &mut self.record else { return };
+        if offset >= self.offset || offset < self.record_start {
+            return;
+        }
+        let mut unread = record.split_off((offset - self.record_start) as usize);
+        unread.extend_from_slice(&self.unread[self.unread_pos..]);
+        self.unread = unread;
+        self.unread_pos = 0;
+        self.offset = offset;
+    }
+
+    fn replay(&mut self, replay: Vec<u8>) {
+        self.replay = replay;
+        self.replay_pos = 0;
+    }
+
+    // consumes the input up to the next <tu> or </body> tag; `false` when the input ends first
+    fn skip_to_resync_point(&mut self) -> io::Result<bool> {
+        loop {
+            let buf = self.fill_buf()?;
+            if buf.is_empty() {
+                return Ok(false);
+            }
+            match buf.iter().position(|b| *b == b'<') {
+                Some(0) => {}
+                Some(len) => {
+                    self.consume(len);
+                    continue;
+                }
+                None => {
+                    let len = buf.len();
+                    self.consume(len);
+                    continue;
+                }
+            }
+
+            let mut candidate = Vec::new();
+            let found = loop {
+                if let Some(found) = resync_point(&candidate) {
+                    break found;
+                }
+                match self.fill_buf()?.first() {
+                    Some(&byte) => {
+                        candidate.push(byte);
+                        self.consume(1);
+                    }
+                    None => break false,
+                }
+            };
+            // the search goes on after the `<` of what is not a resync point
+            let keep = if found { 0 } else { 1.min(candidate.len()) };
+            self.unread_from(self.offset - (candidate.len() - keep) as u64);
+            if found {
+                return Ok(true);
+            }
+        }
+    }
+}
+
+// `Some(true)` when `candidate` starts a <tu> or </body> tag, `Some(false)` when it does not, `None` when it is too
+// short to tell
+fn resync_point(candidate: &[u8]) -> Option<bool> {
+    let mut undecided = false;
+    for name in [b"<tu".as_slice(), b"</body"] {
+        match candidate.get(name.len()) {
+            Some(next) if candidate.starts_with(name) => {
+                if matches!(next, b' ' | b'\t' | b'\r' | b'\n' | b'>' | b'/') {
+                    return Some(true);
+                }
+            }
+            Some(_) => {}
+            None => undecided |= name.starts_with(candidate),
+        }
+    }
+    if undecided { None } else { Some(false) }
+}
+
+impl<R: BufRead> Read for Source<R> {
+    fn read(&mut self, out: &mut [u8]) -> io::Result<usize> {
+        let buf = self.fill_buf()?;
+        let len = buf.len().min(out.len());
+        out[..len].copy_from_slice(&buf[..len]);
+        self.consume(len);
+        Ok(len)
+    }
+}
+
+impl<R: BufRead> BufRead for Source<R> {
+    fn fill_buf(&mut self) -> io::Result<&[u8]> {
+        if self.replay_pos < self.replay.len() {
+            return Ok(&self.replay[self.replay_pos..]);
+        }
+        if self.unread_pos < self.unread.len() {
+            return Ok(&self.unread[self.unread_pos..]);
+        }
+        match &mut self.inner {
+            Some(inner) => inner.fill_buf(),
+            None => Ok(&[]),
+        }
+    }
+
+    fn consume(&mut self, amt: usize) {
+        if self.replay_pos < self.replay.len() {
+            self.replay_pos += amt;
+            return;
+        }
+
+        if self.unread_pos < self.unread.len() {
+            if let Some(record) = &mut self.record {
+                record.extend_from_slice(&self.unread[self.unread_pos..self.unread_pos + amt]);
+            }
+            self.unread_pos += amt;
+        } else if let Some(inner) = &mut self.inner {
+            // the consumed bytes are still at the start of the buffer
+            if let Some(record) = &mut self.record
+                && let Ok(buf) = inner.fill_buf()
+            {
+                record.extend_from_slice(&buf[..amt.min(buf.len())]);
+            }
+            inner.consume(amt);
+        }
+        self.offset += amt as u64;
+    }
+}
+
+impl<R: BufRead> Iterator for TmxReader<R> {
+    type Item = Result<TmxItem>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.read_item().transpose()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pretty_assertions::assert_eq;
+    use crate::element::Element;
+
+    #[test]
+    fn test_reader_typed_tus() {
+        let file = std::fs::File::open("test-data/trim/test.tmx").unwrap();
+        let tus: Vec<Tu> = TmxReader::new(std::io::BufReader::new(file)).tus().collect::<Result<_>>().unwrap();
+
+        assert_eq!(7, tus.len());
+
+        let tu = &tus[1];
+        assert_eq!(Some("2"), tu.attribute("tuid"));
+        assert_eq!(Some("none"), tu.prop("tmgr:docname"));
+        assert_eq!(2, tu.tuvs.len());
+        assert_eq!("de", tu.tuvs[0].lang);
+        assert_eq!("Unser schönes 4 Segment ", tu.tuvs[0].seg_text());
+        assert_eq!(Some("SOME_CONTEXT"), tus[6].prop("tmgr:context"));
+    }
+
+    #[test]
+    fn test_reader_quarantine() {
+        let content = std::fs::read("test-data/trim/malformed.tmx").unwrap();
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("quarantine.xml").to_string_lossy().to_string();
+
+        // a small buffer, so that resync points straddle reads
+        let reader = |content: &[u8], quarantine: Option<&Quarantine>| {
+            TmxReader::new(std::io::BufReader::with_capacity(5, std::io::Cursor::new(content.to_vec())))
+                .with_quarantine(quarantine, "malformed.tmx")
+        };
+
+        let quarantine = Quarantine::create(&path).unwrap();
+        let tus: Vec<Tu> = reader(&content, Some(&quarantine)).tus().collect::<Result<_>>().unwrap();
+        let tuids: Vec<&str> = tus.iter().filter_map(|tu| tu.attribute("tuid")).collect();
+        assert_eq!(tuids, vec!["1", "3", "6"]);
+        // offsets are those of the input, and the bindings of <tmx> are known after recovering
+        for tu in &tus {
+            let raw = &content[tu.span.start as usize..tu.span.end as usize];
+            assert!(raw.starts_with(b"<tu ") && raw.ends_with(b"</tu>"), "{}", String::from_utf8_lossy(raw));
+        }
+        assert!(tus[2].namespaces.contains(&("t5".to_string(), "urn:t5".to_string())));
+
+        assert_eq!(quarantine.finish().unwrap(), 3);
+        let quarantined = std::fs::read_to_string(&path).unwrap();
+        assert!(quarantined.starts_with(
+            "<!-- malformed.tmx, byte 365: XML parse error at 413: ill-formed document: expected `</seg>`, but `</tuv>` was found -->\n<tu tuid=\"2\">"
+        ), "{}", quarantined);
+        assert!(quarantined.contains(
+            "<!-- malformed.tmx, byte 603: Malformed TMX: <tu> not closed before the next <tu> -->\n<tu tuid=\"4\">\n      <tuv xml:lang=\"de\"><seg>Vier</seg></tuv>\n<!-- malformed.tmx, byte 668: "
+        ), "{}", quarantined);
+
+        assert!(reader(&content, None).tus().collect::<Result<Vec<_>>>().is_err());
+
+        // an input that ends inside <body> is closed
+        let quarantine = Quarantine::create(&path).unwrap();
+        let items: Vec<TmxItem> = reader(&content[..870], Some(&quarantine)).collect::<Result<_>>().unwrap();
+        let ends: Vec<String> = items.iter().rev().take(2)
+            .filter_map(|item| match item {
+                TmxItem::Event(Event::End(e)) => Some(String::from_utf8_lossy(e.name().as_ref()).into_owned()),
+                _ => None,
+            })
+            .collect();
+        assert_eq!(ends, vec!["tmx", "body"]);
+        assert_eq!(quarantine.finish().unwrap(), 4);
+    }
+}
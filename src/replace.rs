@@ -0,0 +1,184 @@
+use anyhow::{Context, Result};
+use chrono::{DateTime, Utc};
+use quick_xml::events::{BytesText, Event};
+use regex::{Regex, RegexBuilder};
+
+use crate::date::format_date;
+use crate::element::Element;
+use crate::io::{create_output, open_input};
+use crate::reader::{TmxItem, TmxReader};
+use crate::tu::{event_text, Tu};
+use crate::writer::TmxWriter;
+
+/// Replaces every match of `pattern` with `replacement`, which may refer to groups as `$1` or `${name}`.
+#[derive(Clone, Debug)]
+pub struct Rule {
+    pub pattern: Regex,
+    pub replacement: String,
+}
+
+impl Rule {
+    pub fn new(pattern: &str, replacement: &str, ignore_case: bool) -> Result<Rule> {
+        let pattern = RegexBuilder::new(pattern)
+            .case_insensitive(ignore_case)
+            .build()
+            .context(format!("Invalid pattern '{}'", pattern))?;
+        Ok(Rule { pattern, replacement: replacement.to_string() })
+    }
+}
+
+/// Reads rules from a file with one `pattern<TAB>replacement` per line.
+/// Empty lines and lines starting with `#` are skipped.
+pub fn read_rules(path: &str, ignore_case: bool) -> Result<Vec<Rule>> {
+    let content = std::fs::read_to_string(path).context(format!("Cannot read rules file {}", path))?;
+
+    content.lines()
+        .enumerate()
+        .filter(|(_, line)| !line.trim().is_empty() && !line.starts_with('#'))
+        .map(|(i, line)| {
+            let (pattern, replacement) = line.split_once('\t')
+                .ok_or_else(|| anyhow::anyhow!("{}:{}: expected pattern<TAB>replacement", path, i + 1))?;
+            Rule::new(pattern, replacement, ignore_case)
+        })
+        .collect()
+}
+
+pub struct ReplaceOptions {
+    pub rules: Vec<Rule>,
+    /// Only segments in this language; `en` also matches `en-US`. All segments when `None`.
+    pub lang: Option<String>,
+    /// Written to `changedate` and `changeid` of every TU that was changed.
+    pub change_date: DateTime<Utc>,
+    pub change_id: String,
+}
+
+impl ReplaceOptions {
+    /// Applies the rules, in order, to the segments of `tu`. Returns for every rule whether it changed the TU.
+    ///
+    /// Rules only see the text directly inside `<seg>`, one run between two inline elements at a time,
+    /// so inline elements and the native code inside them are never changed.
+    pub fn apply(&self, tu: &mut Tu) -> Vec<bool> {
+        let mut changed = vec![false; self.rules.len()];
+        let mut events = Vec::with_capacity(tu.events.len());
+        let mut run: Vec<Event<'static>> = Vec::new();
+
+        // element names from <tu> down, innermost last
+        let mut path: Vec<Vec<u8>> = Vec::new();
+        let mut tuv_index = 0;
+        let mut in_lang = false;
+
+        for ev in std::mem::take(&mut tu.events) {
+            let is_text = matches!(ev, Event::Text(_) | Event::CData(_) | Event::GeneralRef(_));
+            if is_text && in_lang && path.len() == 3 && path[1] == b"tuv" && path[2] == b"seg" {
+                run.push(ev);
+                continue;
+            }
+
+            self.replace_run(&mut run, &mut events, &mut changed);
+
+            match &ev {
+                Event::Start(e) => {
+                    if path.len() == 1 && e.name().as_ref() == b"tuv" {
+                        in_lang = tu.tuvs.get(tuv_index)
+                            .is_some_and(|tuv| self.lang.as_deref().is_none_or(|lang| tuv.has_lang(lang)));
+                        tuv_index += 1;
+                    }
+                    path.push(e.name().as_ref().to_vec());
+                }
+                Event::Empty(e) if path.len() == 1 && e.name().as_ref() == b"tuv" => tuv_index += 1,
+                Event::End(_) => {
+                    path.pop();
+                }
+                _ => {}
+            }
+            events.push(ev);
+        }
+        self.replace_run(&mut run, &mut events, &mut changed);
+
+        if changed.contains(&true) {
+            tu.set_events(events);
+            tu.set_attribute("changedate", &format_date(&self.change_date));
+            tu.set_attribute("changeid", &self.change_id);
+        } else {
+            tu.events = events;
+        }
+
+        changed
+    }
+
+    // a run of text and references is replaced by a single text event when a rule changed it
+    fn replace_run(&self, run: &mut Vec<Event<'static>>, events: &mut Vec<Event<'static>>, changed: &mut [bool]) {
+        if run.is_empty() {
+            return;
+        }
+
+        let text: String = run.iter().filter_map(event_text).collect();
+        let mut replaced = text.clone();
+        for (i, rule) in self.rules.iter().enumerate() {
+            let result = rule.pattern.replace_all(&replaced, rule.replacement.as_str()).into_owned();
+            if result != replaced {
+                changed[i] = true;
+                replaced = result;
+            }
+        }
+
+        if replaced == text {
+            events.append(run);
+        } else {
+            events.push(Event::Text(BytesText::new(&replaced).into_owned()));
+            run.clear();
+        }
+    }
+}
+
+/// Applies `options` to every TU of `input`. Returns the number of TUs each rule changed.
+pub fn replace(input: &str, output: &str, options: &ReplaceOptions) -> Result<Vec<usize>> {
+    let mut reader = TmxReader::new(open_input(input).context("Cannot open input file")?);
+
+    let mut writer = TmxWriter::new(create_output(output).context("Cannot create output file")?);
+
+    let mut counts = vec![0; options.rules.len()];
+
+    while let Some(item) = reader.read_item()? {
+        match item {
+            TmxItem::Tu(mut tu) => {
+                for (count, changed) in counts.iter_mut().zip(options.apply(&mut tu)) {
+                    *count += changed as usize;
+                }
+                writer.write_tu(&tu)?;
+            }
+            TmxItem::Event(ev) => writer.write_event(ev)?,
+        }
+    }
+
+    writer.into_inner().finish()?;
+    Ok(counts)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pretty_assertions::assert_eq;
+
+    #[test]
+    fn test_replace() {
+        let options = ReplaceOptions {
+            rules: vec![
+                Rule::new(r"nice (\d)", "great $1", false).unwrap(),
+                Rule::new("SEGMENT 1", "sentence", true).unwrap(),
+                Rule::new("n=", "x=", false).unwrap(),
+            ],
+            lang: Some("en".to_string()),
+            change_date: DateTime::from_timestamp(1_700_000_000, 0).unwrap(),
+            change_id: "tester".to_string(),
+        };
+        let counts = replace("test-data/replace/test.tmx", "test-data/replace/replaced.tmx", &options).unwrap();
+
+        let expected = std::fs::read_to_string("test-data/replace/expected.tmx").unwrap();
+        let output = std::fs::read_to_string("test-data/replace/replaced.tmx").unwrap();
+        std::fs::remove_file("test-data/replace/replaced.tmx").unwrap();
+
+        assert_eq!(vec![3, 2, 0], counts);
+        assert_eq!(expected, output);
+    }
+}
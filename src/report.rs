@@ -0,0 +1,195 @@
+use anyhow::{Context, Result};
+use serde_json::json;
+use std::collections::BTreeMap;
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use std::str::FromStr;
+
+use crate::element::Element;
+use crate::key::{source_text, target_text};
+use crate::tu::Tu;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ReportFormat {
+    Csv,
+    Json,
+}
+
+impl ReportFormat {
+    /// JSON for `.json` files, CSV otherwise.
+    pub fn from_path(path: &str) -> ReportFormat {
+        if path.to_ascii_lowercase().ends_with(".json") {
+            ReportFormat::Json
+        } else {
+            ReportFormat::Csv
+        }
+    }
+}
+
+impl FromStr for ReportFormat {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "csv" => Ok(ReportFormat::Csv),
+            "json" => Ok(ReportFormat::Json),
+            _ => Err(anyhow::anyhow!("Unknown report format '{}'. Expected csv or json", s)),
+        }
+    }
+}
+
+pub struct ReportOptions {
+    pub path: String,
+    pub format: ReportFormat,
+}
+
+/// The identifying fields of a TU as they appear in the report.
+#[derive(Clone, Debug)]
+pub struct TuSummary {
+    pub tuid: String,
+    pub creationdate: String,
+    pub creationid: String,
+    pub source: String,
+    pub target: String,
+}
+
+impl TuSummary {
+    pub fn new(tu: &Tu, source_lang: Option<&str>) -> Self {
+        TuSummary {
+            tuid: tu.attribute("tuid").unwrap_or_default().to_string(),
+            creationdate: tu.attribute("creationdate").unwrap_or_default().to_string(),
+            creationid: tu.attribute("creationid").unwrap_or_default().to_string(),
+            source: source_text(tu, source_lang),
+            target: target_text(tu, source_lang),
+        }
+    }
+
+    fn to_json(&self) -> serde_json::Value {
+        json!({
+            "tuid": self.tuid,
+            "creationdate": self.creationdate,
+            "creationid": self.creationid,
+            "source": self.source,
+            "target": self.target,
+        })
+    }
+}
+
+/// TUs that share a key: the one that was kept and the ones that were dropped.
+pub struct DuplicateGroup {
+    pub key: Vec<(String, String)>,
+    pub kept: TuSummary,
+    pub dropped: Vec<TuSummary>,
+}
+
+/// Surviving TUs with the same source text but different targets.
+pub struct Conflict {
+    pub source: String,
+    pub tus: Vec<TuSummary>,
+}
+
+#[derive(Default)]
+pub struct Report {
+    pub duplicates: Vec<DuplicateGroup>,
+    pub conflicts: Vec<Conflict>,
+}
+
+impl Report {
+    /// Groups the surviving TUs by source text and records those with differing targets.
+    pub fn find_conflicts(&mut self, survivors: impl IntoIterator<Item = TuSummary>) {
+        let mut by_source: BTreeMap<String, Vec<TuSummary>> = BTreeMap::new();
+        for summary in survivors {
+            by_source.entry(summary.source.clone()).or_default().push(summary);
+        }
+
+        for (source, tus) in by_source {
+            if tus.iter().any(|tu| tu.target != tus[0].target) {
+                self.conflicts.push(Conflict { source, tus });
+            }
+        }
+    }
+
+    pub fn write(&self, options: &ReportOptions) -> Result<()> {
+        let file = File::create(&options.path).context(format!("Cannot create report file {}", options.path))?;
+        let mut out = BufWriter::new(file);
+
+        match options.format {
+            ReportFormat::Csv => self.write_csv(&mut out)?,
+            ReportFormat::Json => self.write_json(&mut out)?,
+        }
+
+        out.flush()?;
+        Ok(())
+    }
+
+    fn write_json(&self, out: &mut impl Write) -> Result<()> {
+        let duplicates: Vec<serde_json::Value> = self.duplicates.iter().map(|group| {
+            let key: serde_json::Map<String, serde_json::Value> = group.key.iter()
+                .map(|(field, value)| (field.clone(), json!(value)))
+                .collect();
+            json!({
+                "key": key,
+                "kept": group.kept.to_json(),
+                "dropped": group.dropped.iter().map(TuSummary::to_json).collect::<Vec<_>>(),
+            })
+        }).collect();
+
+        let conflicts: Vec<serde_json::Value> = self.conflicts.iter().map(|conflict| {
+            json!({
+                "source": conflict.source,
+                "tus": conflict.tus.iter().map(TuSummary::to_json).collect::<Vec<_>>(),
+            })
+        }).collect();
+
+        serde_json::to_writer_pretty(&mut *out, &json!({ "duplicates": duplicates, "conflicts": conflicts }))?;
+        writeln!(out)?;
+        Ok(())
+    }
+
+    // one row per TU; rows of the same duplicate group or conflict share the group number
+    fn write_csv(&self, out: &mut impl Write) -> Result<()> {
+        writeln!(out, "section,group,status,tuid,creationdate,creationid,key,source,target")?;
+
+        for (i, group) in self.duplicates.iter().enumerate() {
+            let key: Vec<String> = group.key.iter().map(|(field, value)| format!("{}={}", field, value)).collect();
+            let key = key.join("; ");
+
+            write_csv_row(out, "duplicate", i + 1, "kept", &group.kept, &key)?;
+            for dropped in &group.dropped {
+                write_csv_row(out, "duplicate", i + 1, "dropped", dropped, &key)?;
+            }
+        }
+
+        for (i, conflict) in self.conflicts.iter().enumerate() {
+            for tu in &conflict.tus {
+                write_csv_row(out, "conflict", i + 1, "kept", tu, "")?;
+            }
+        }
+
+        Ok(())
+    }
+}
+
+fn write_csv_row(out: &mut impl Write, section: &str, group: usize, status: &str, tu: &TuSummary, key: &str) -> Result<()> {
+    let fields = [
+        section.to_string(),
+        group.to_string(),
+        status.to_string(),
+        csv_field(&tu.tuid),
+        csv_field(&tu.creationdate),
+        csv_field(&tu.creationid),
+        csv_field(key),
+        csv_field(&tu.source),
+        csv_field(&tu.target),
+    ];
+    writeln!(out, "{}", fields.join(","))?;
+    Ok(())
+}
+
+fn csv_field(value: &str) -> String {
+    if value.contains([',', '"', '\n', '\r']) {
+        format!("\"{}\"", value.replace('"', "\"\""))
+    } else {
+        value.to_string()
+    }
+}
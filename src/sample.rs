@@ -0,0 +1,439 @@
+use anyhow::{Context, Result};
+use quick_xml::events::Event;
+use std::fmt;
+use std::io::Write;
+use std::str::FromStr;
+
+use crate::io::{create_output, open_input, Output};
+use crate::key::source_text;
+use crate::reader::{TmxItem, TmxReader};
+use crate::tu::Tu;
+use crate::whitespace::is_xml_whitespace;
+use crate::writer::TmxWriter;
+
+/// SplitMix64, written out here so that a seed selects the same TUs in every release.
+struct Random(u64);
+
+impl Random {
+    fn next_u64(&mut self) -> u64 {
+        self.0 = self.0.wrapping_add(0x9e37_79b9_7f4a_7c15);
+        let mut z = self.0;
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+        z ^ (z >> 31)
+    }
+
+    /// A number in `0..n`.
+    fn below(&mut self, n: u64) -> u64 {
+        ((self.next_u64() as u128 * n as u128) >> 64) as u64
+    }
+
+    /// A number in `[0, 1)`.
+    fn fraction(&mut self) -> f64 {
+        (self.next_u64() >> 11) as f64 / (1u64 << 53) as f64
+    }
+}
+
+// FNV-1a, mixed with the seed: stable across releases, unlike std's hashers
+fn text_fraction(seed: u64, text: &str) -> f64 {
+    let hash = text.bytes().fold(0xcbf2_9ce4_8422_2325u64, |hash, b| (hash ^ b as u64).wrapping_mul(0x0100_0000_01b3));
+    Random(seed ^ hash).fraction()
+}
+
+/// How many TUs `sample` keeps.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum SampleSize {
+    /// Exactly this many, or all TUs when there are fewer.
+    Count(usize),
+    /// Every TU with this probability, in percent, so about this share of the TUs.
+    Percent(f64),
+}
+
+impl FromStr for SampleSize {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s.strip_suffix('%') {
+            Some(percent) => match percent.trim().parse::<f64>() {
+                Ok(percent) if (0.0..=100.0).contains(&percent) => Ok(SampleSize::Percent(percent)),
+                _ => Err(anyhow::anyhow!("Invalid percentage '{}'. Expected a number from 0 to 100 followed by %", s)),
+            },
+            None => s.trim().parse().map(SampleSize::Count)
+                .map_err(|_| anyhow::anyhow!("Invalid sample size '{}'. Expected a number of TUs or a percentage like 5%", s)),
+        }
+    }
+}
+
+impl fmt::Display for SampleSize {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SampleSize::Count(n) => write!(f, "{}", n),
+            SampleSize::Percent(percent) => write!(f, "{}%", percent),
+        }
+    }
+}
+
+/// Writes a random sample of the TUs of `input`, in input order; everything outside of TUs is copied.
+///
+/// A count is drawn by reservoir sampling, which holds that many TUs in memory; a percentage keeps or drops each TU
+/// as it is read. The same `seed` draws the same sample from the same input. Returns the number of TUs written.
+pub fn sample(input: &str, output: &str, size: SampleSize, seed: u64) -> Result<usize> {
+    let mut reader = TmxReader::new(open_input(input).context("Cannot open input file")?);
+
+    let mut writer = TmxWriter::new(create_output(output).context("Cannot create output file")?);
+
+    let mut random = Random(seed);
+    let mut index = 0usize;
+    // whitespace goes with the item that follows it
+    let mut pending: Option<Event<'static>> = None;
+    // position, whitespace in front and TU of every sampled TU, when drawing a count
+    let mut reservoir: Vec<(usize, Option<Event<'static>>, Tu)> = Vec::new();
+    let mut written = 0;
+
+    while let Some(item) = reader.read_item()? {
+        match item {
+            TmxItem::Event(Event::Text(t)) if is_xml_whitespace(t.as_ref()) => {
+                if let Some(ws) = pending.replace(Event::Text(t)) {
+                    writer.write_event(ws)?;
+                }
+            }
+            TmxItem::Tu(tu) => {
+                let ws = pending.take();
+                match size {
+                    SampleSize::Count(n) if index < n => reservoir.push((index, ws, tu)),
+                    SampleSize::Count(n) => {
+                        let j = random.below(index as u64 + 1) as usize;
+                        if j < n {
+                            reservoir[j] = (index, ws, tu);
+                        }
+                    }
+                    SampleSize::Percent(percent) => {
+                        if random.fraction() * 100.0 < percent {
+                            if let Some(ws) = ws {
+                                writer.write_event(ws)?;
+                            }
+                            writer.write_tu(&tu)?;
+                            written += 1;
+                        }
+                    }
+                }
+                index += 1;
+            }
+            TmxItem::Event(ev) => {
+                if matches!(ev, Event::End(ref e) if e.name().as_ref() == b"body") {
+                    reservoir.sort_by_key(|(index, _, _)| *index);
+                    for (_, ws, tu) in reservoir.drain(..) {
+                        if let Some(ws) = ws {
+                            writer.write_event(ws)?;
+                        }
+                        writer.write_tu(&tu)?;
+                        written += 1;
+                    }
+                }
+                if let Some(ws) = pending.take() {
+                    writer.write_event(ws)?;
+                }
+                writer.write_event(ev)?;
+            }
+        }
+    }
+    if let Some(ws) = pending.take() {
+        writer.write_event(ws)?;
+    }
+
+    writer.into_inner().finish()?;
+    Ok(written)
+}
+
+pub const PARTS: [&str; 3] = ["train", "dev", "test"];
+
+/// Output format of `partition`.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum PartitionFormat {
+    #[default]
+    Tmx,
+    /// Plain text, one segment per line, in one file per part and language: the source language and the first
+    /// target language of the first TU.
+    Text,
+}
+
+impl FromStr for PartitionFormat {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "tmx" => Ok(PartitionFormat::Tmx),
+            "text" => Ok(PartitionFormat::Text),
+            _ => Err(anyhow::anyhow!("Unknown format '{}'. Expected tmx or text", s)),
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct PartitionOptions {
+    /// Shares of train, dev and test; they need not add up to 1.
+    pub ratios: [f64; 3],
+    pub seed: u64,
+    /// Put all TUs with the same source text in the same part.
+    pub same_source: bool,
+    pub format: PartitionFormat,
+}
+
+impl Default for PartitionOptions {
+    fn default() -> Self {
+        PartitionOptions { ratios: [0.8, 0.1, 0.1], seed: 0, same_source: false, format: PartitionFormat::Tmx }
+    }
+}
+
+/// Parses train:dev:test ratios like `80:10:10` or `0.9:0.05:0.05`.
+pub fn parse_ratios(s: &str) -> Result<[f64; 3]> {
+    let ratios = s.split(':').map(|ratio| ratio.trim().parse::<f64>()).collect::<Result<Vec<f64>, _>>();
+    match ratios.as_deref() {
+        Ok(&[train, dev, test]) if [train, dev, test].iter().all(|r| *r >= 0.0) && train + dev + test > 0.0 => {
+            Ok([train, dev, test])
+        }
+        _ => Err(anyhow::anyhow!("Invalid ratios '{}'. Expected train:dev:test like 80:10:10", s)),
+    }
+}
+
+enum PartWriter {
+    Tmx(TmxWriter<Output>),
+    Text { source: Output, target: Output },
+}
+
+/// Writes the TUs of `input` to train, dev and test files named after `pattern`, in one pass.
+///
+/// `{part}` in the pattern is `train`, `dev` or `test`; plain text also needs `{lang}`. Every TMX file gets everything
+/// outside of the TUs. Parts with a ratio of 0 are not written. Returns the name and number of TUs of every part.
+pub fn partition(input: &str, pattern: &str, options: &PartitionOptions) -> Result<Vec<(String, usize)>> {
+    if !pattern.contains("{part}") {
+        return Err(anyhow::anyhow!("The output pattern '{}' must contain {{part}}", pattern));
+    }
+    if options.format == PartitionFormat::Text && !pattern.contains("{lang}") {
+        return Err(anyhow::anyhow!("The output pattern '{}' must contain {{lang}} for plain text", pattern));
+    }
+
+    let total: f64 = options.ratios.iter().sum();
+    let thresholds = [options.ratios[0] / total, (options.ratios[0] + options.ratios[1]) / total];
+    // fractions above the thresholds, which rounding can leave below 1, go to the last part that is written
+    let last = options.ratios.iter().rposition(|ratio| *ratio > 0.0).unwrap_or(2);
+
+    let mut reader = TmxReader::new(open_input(input).context("Cannot open input file")?);
+
+    let mut random = Random(options.seed);
+    let mut writers: Vec<Option<PartWriter>> = Vec::new();
+    let mut counts = [0usize; 3];
+    let mut langs: Option<(String, String)> = None;
+    let mut incomplete = 0;
+    // whitespace goes with the item that follows it
+    let mut pending: Option<Event<'static>> = None;
+    // events in front of the first TU, written to each TMX file once it is created
+    let mut prolog: Vec<Event<'static>> = Vec::new();
+
+    while let Some(item) = reader.read_item()? {
+        let tu = match item {
+            TmxItem::Event(Event::Text(t)) if is_xml_whitespace(t.as_ref()) => {
+                if let Some(ws) = pending.replace(Event::Text(t)) {
+                    write_all(&mut writers, &mut prolog, ws)?;
+                }
+                continue;
+            }
+            TmxItem::Event(ev) => {
+                if let Some(ws) = pending.take() {
+                    write_all(&mut writers, &mut prolog, ws)?;
+                }
+                write_all(&mut writers, &mut prolog, ev)?;
+                continue;
+            }
+            TmxItem::Tu(tu) => tu,
+        };
+
+        let ws = pending.take();
+        if writers.is_empty() {
+            if options.format == PartitionFormat::Text {
+                let source = tu.tuvs.first().map(|tuv| tuv.lang.clone()).unwrap_or_default();
+                let target = tu.tuvs.iter().find(|tuv| tuv.lang != source).map(|tuv| tuv.lang.clone()).unwrap_or_default();
+                langs = Some((source, target));
+            }
+            writers = open_parts(pattern, options, langs.as_ref(), &prolog)?;
+        }
+
+        let source_lang = langs.as_ref().map(|(source, _)| source.as_str())
+            .or_else(|| tu.tuvs.first().map(|tuv| tuv.lang.as_str()));
+        let fraction = if options.same_source {
+            text_fraction(options.seed, &source_text(&tu, source_lang))
+        } else {
+            random.fraction()
+        };
+        let part = thresholds.iter().position(|threshold| fraction < *threshold).unwrap_or(last);
+        let writer = writers[part].as_mut().expect("parts with a ratio above 0 are open");
+
+        match writer {
+            PartWriter::Tmx(writer) => {
+                if let Some(ws) = ws {
+                    writer.write_event(ws)?;
+                }
+                writer.write_tu(&tu)?;
+            }
+            PartWriter::Text { source, target } => {
+                let (source_lang, target_lang) = langs.as_ref().unwrap();
+                let text = |lang: &String| tu.tuvs.iter().find(|tuv| &tuv.lang == lang).map(|tuv| tuv.seg_text());
+                match (text(source_lang), text(target_lang)) {
+                    (Some(source_text), Some(target_text)) => {
+                        writeln!(source, "{}", source_text.replace(['\r', '\n', '\t'], " "))?;
+                        writeln!(target, "{}", target_text.replace(['\r', '\n', '\t'], " "))?;
+                    }
+                    _ => {
+                        incomplete += 1;
+                        continue;
+                    }
+                }
+            }
+        }
+        counts[part] += 1;
+    }
+    if let Some(ws) = pending.take() {
+        write_all(&mut writers, &mut prolog, ws)?;
+    }
+
+    if writers.is_empty() {
+        return Err(anyhow::anyhow!("No <tu> elements found in {}", input));
+    }
+    if incomplete > 0 {
+        let (source, target) = langs.unwrap_or_default();
+        eprintln!("Warning: left out {} TUs without both a {} and a {} segment", incomplete, source, target);
+    }
+
+    let mut parts = Vec::new();
+    for ((name, writer), count) in PARTS.iter().zip(writers).zip(counts) {
+        match writer {
+            Some(PartWriter::Tmx(writer)) => writer.into_inner().finish()?,
+            Some(PartWriter::Text { source, target }) => {
+                source.finish()?;
+                target.finish()?;
+            }
+            None => continue,
+        }
+        parts.push((name.to_string(), count));
+    }
+    Ok(parts)
+}
+
+fn open_parts(
+    pattern: &str,
+    options: &PartitionOptions,
+    langs: Option<&(String, String)>,
+    prolog: &[Event<'static>],
+) -> Result<Vec<Option<PartWriter>>> {
+    let create = |part: &str, lang: &str| {
+        let path = pattern.replace("{part}", part).replace("{lang}", lang);
+        create_output(&path).context(format!("Cannot create output file {}", path))
+    };
+
+    PARTS.iter().zip(options.ratios).map(|(part, ratio)| {
+        if ratio <= 0.0 {
+            return Ok(None);
+        }
+        Ok(Some(match langs {
+            Some((source, target)) => PartWriter::Text { source: create(part, source)?, target: create(part, target)? },
+            None => {
+                let mut writer = TmxWriter::new(create(part, "")?);
+                for ev in prolog {
+                    writer.write_event(ev.borrow())?;
+                }
+                PartWriter::Tmx(writer)
+            }
+        }))
+    }).collect()
+}
+
+// everything outside of TUs goes to every TMX file, or is kept until they are created
+fn write_all(writers: &mut [Option<PartWriter>], prolog: &mut Vec<Event<'static>>, ev: Event<'static>) -> Result<()> {
+    if writers.is_empty() {
+        prolog.push(ev);
+        return Ok(());
+    }
+    for writer in writers.iter_mut().flatten() {
+        if let PartWriter::Tmx(writer) = writer {
+            writer.write_event(ev.borrow())?;
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pretty_assertions::assert_eq;
+    use crate::element::Element;
+
+    fn tuids(path: &str) -> Vec<String> {
+        let tuids = TmxReader::new(open_input(path).unwrap()).tus()
+            .map(|tu| tu.unwrap().attribute("tuid").unwrap_or_default().to_string())
+            .collect();
+        std::fs::remove_file(path).unwrap();
+        tuids
+    }
+
+    #[test]
+    fn test_parse_sample_size() {
+        assert_eq!(SampleSize::Count(100), "100".parse().unwrap());
+        assert_eq!(SampleSize::Percent(2.5), "2.5%".parse().unwrap());
+        assert!("120%".parse::<SampleSize>().is_err());
+        assert!("many".parse::<SampleSize>().is_err());
+        assert_eq!([80.0, 10.0, 10.0], parse_ratios("80:10:10").unwrap());
+        assert!(parse_ratios("80:20").is_err());
+        assert!(parse_ratios("0:0:0").is_err());
+    }
+
+    #[test]
+    fn test_sample() {
+        let dir = tempfile::tempdir().unwrap();
+        let output = dir.path().join("sample.tmx").to_string_lossy().to_string();
+
+        assert_eq!(3, sample("test-data/trim/test.tmx", &output, SampleSize::Count(3), 7).unwrap());
+        let first = tuids(&output);
+        sample("test-data/trim/test.tmx", &output, SampleSize::Count(3), 7).unwrap();
+        assert_eq!(first, tuids(&output));
+        // in input order
+        let mut sorted = first.clone();
+        sorted.sort();
+        assert_eq!(sorted, first);
+
+        assert_eq!(7, sample("test-data/trim/test.tmx", &output, SampleSize::Count(10), 7).unwrap());
+        assert_eq!(7, tuids(&output).len());
+        assert_eq!(0, sample("test-data/trim/test.tmx", &output, SampleSize::Percent(0.0), 7).unwrap());
+        assert_eq!(7, sample("test-data/trim/test.tmx", &output, SampleSize::Percent(100.0), 7).unwrap());
+    }
+
+    #[test]
+    fn test_partition() {
+        let dir = tempfile::tempdir().unwrap();
+        let pattern = dir.path().join("corpus.{part}.tmx").to_string_lossy().to_string();
+        let path = |part: &str| pattern.replace("{part}", part);
+
+        // test-data/filter/test.tmx has one source text in every TU
+        let options = PartitionOptions { ratios: [1.0, 1.0, 1.0], same_source: true, ..Default::default() };
+        let parts = partition("test-data/filter/test.tmx", &pattern, &options).unwrap();
+        assert_eq!(1, parts.iter().filter(|(_, count)| *count == 6).count());
+        assert_eq!(6, parts.iter().map(|(_, count)| count).sum::<usize>());
+        for part in PARTS {
+            std::fs::remove_file(path(part)).unwrap();
+        }
+
+        let options = PartitionOptions { ratios: [1.0, 0.0, 1.0], seed: 3, ..Default::default() };
+        let parts = partition("test-data/trim/test.tmx", &pattern, &options).unwrap();
+        assert_eq!(vec!["train", "test"], parts.iter().map(|(name, _)| name.as_str()).collect::<Vec<_>>());
+        let mut all = [tuids(&path("train")), tuids(&path("test"))].concat();
+        all.sort();
+        assert_eq!(vec!["1", "2", "3", "4", "5", "6", "7"], all);
+        assert!(!std::path::Path::new(&path("dev")).exists());
+
+        let text_pattern = dir.path().join("corpus.{part}.{lang}").to_string_lossy().to_string();
+        let options = PartitionOptions { ratios: [1.0, 0.0, 0.0], format: PartitionFormat::Text, ..Default::default() };
+        partition("test-data/split/test.tmx", &text_pattern, &options).unwrap();
+        let text = |lang: &str| std::fs::read_to_string(text_pattern.replace("{part}", "train").replace("{lang}", lang)).unwrap();
+        assert_eq!("Hallo Welt\nEin  Segment\nTschüss\n", text("de-DE"));
+        assert_eq!("Hello world\nA  segment\nBye\n", text("en-US"));
+    }
+}
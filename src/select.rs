@@ -0,0 +1,244 @@
+use anyhow::{Context, Result};
+use chrono::{DateTime, Utc};
+use std::fmt;
+use std::str::FromStr;
+
+use crate::date::{format_date, parse_date, tu_date};
+use crate::element::Element;
+use crate::io::{create_output, open_input};
+use crate::reader::{TmxItem, TmxReader};
+use crate::tu::{is_user_id, Tu};
+use crate::writer::TmxWriter;
+
+/// A condition on a single TU.
+///
+/// Ranges are written `from..to` with either end optional; `from` is included, `to` is not.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Predicate {
+    /// A date attribute (`creationdate`, `changedate`, `lastusagedate`) in a range.
+    /// TUs without the attribute never match.
+    Date {
+        attribute: String,
+        from: Option<DateTime<Utc>>,
+        to: Option<DateTime<Utc>>,
+    },
+    /// A `<tu>` attribute equal to a value. User ids are compared case-insensitively.
+    Attribute { name: String, value: String },
+    /// The value of a `<prop type="...">` equal to a value.
+    Prop { prop_type: String, value: String },
+    /// A `<tuv>` in this language. `en` also matches `en-US`; case is ignored.
+    Lang(String),
+    /// A numeric `tuid` in a range.
+    Tuid { from: Option<u64>, to: Option<u64> },
+}
+
+impl Predicate {
+    pub fn matches(&self, tu: &Tu) -> bool {
+        match self {
+            Predicate::Date { attribute, from, to } => tu_date(tu, attribute).is_some_and(|date| {
+                from.is_none_or(|from| date >= from) && to.is_none_or(|to| date < to)
+            }),
+            Predicate::Attribute { name, value } if is_user_id(name) => {
+                tu.attribute(name).is_some_and(|v| v.eq_ignore_ascii_case(value))
+            }
+            Predicate::Attribute { name, value } => tu.attribute(name) == Some(value.as_str()),
+            Predicate::Prop { prop_type, value } => {
+                tu.props.iter().any(|prop| &prop.prop_type == prop_type && &prop.value == value)
+            }
+            Predicate::Lang(lang) => tu.tuvs.iter().any(|tuv| tuv.has_lang(lang)),
+            Predicate::Tuid { from, to } => {
+                tu.attribute("tuid").and_then(|tuid| tuid.trim().parse::<u64>().ok()).is_some_and(|tuid| {
+                    from.is_none_or(|from| tuid >= from) && to.is_none_or(|to| tuid < to)
+                })
+            }
+        }
+    }
+}
+
+// "from..to", either side may be empty
+fn parse_range<T>(s: &str, parse: impl Fn(&str) -> Result<T>) -> Result<(Option<T>, Option<T>)> {
+    let Some((from, to)) = s.split_once("..") else {
+        return Err(anyhow::anyhow!("Expected a range like from..to, got '{}'", s));
+    };
+    let bound = |value: &str| if value.trim().is_empty() { Ok(None) } else { parse(value.trim()).map(Some) };
+    Ok((bound(from)?, bound(to)?))
+}
+
+fn date_predicate(attribute: &str, range: &str) -> Result<Predicate> {
+    let (from, to) = parse_range(range, parse_date)?;
+    Ok(Predicate::Date { attribute: attribute.to_string(), from, to })
+}
+
+fn equals_predicate(s: &str) -> Result<(String, String)> {
+    match s.split_once('=') {
+        Some((name, value)) if !name.is_empty() => Ok((name.to_string(), value.to_string())),
+        _ => Err(anyhow::anyhow!("Expected <name>=<value>, got '{}'", s)),
+    }
+}
+
+impl FromStr for Predicate {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let Some((field, value)) = s.trim().split_once(':') else {
+            return Err(anyhow::anyhow!("Expected <field>:<value>, got '{}'", s));
+        };
+
+        match field {
+            "created" => date_predicate("creationdate", value),
+            "changed" => date_predicate("changedate", value),
+            "used" => date_predicate("lastusagedate", value),
+            "author" => Ok(Predicate::Attribute { name: "creationid".to_string(), value: value.to_string() }),
+            "changer" => Ok(Predicate::Attribute { name: "changeid".to_string(), value: value.to_string() }),
+            "docname" => Ok(Predicate::Prop { prop_type: "tmgr:docname".to_string(), value: value.to_string() }),
+            "context" => Ok(Predicate::Prop { prop_type: "tmgr:context".to_string(), value: value.to_string() }),
+            "lang" if !value.is_empty() => Ok(Predicate::Lang(value.to_string())),
+            "tuid" if value.contains("..") => {
+                let (from, to) = parse_range(value, |n| n.parse().context(format!("Invalid tuid '{}'", n)))?;
+                Ok(Predicate::Tuid { from, to })
+            }
+            "tuid" => Ok(Predicate::Attribute { name: "tuid".to_string(), value: value.to_string() }),
+            "prop" => equals_predicate(value).map(|(prop_type, value)| Predicate::Prop { prop_type, value }),
+            "attr" => equals_predicate(value).map(|(name, value)| Predicate::Attribute { name, value }),
+            _ => Err(anyhow::anyhow!(
+                "Unknown predicate '{}'. Expected created:, changed:, used:, author:, changer:, docname:, context:, lang:, tuid:, prop:<type>= or attr:<name>=",
+                s
+            )),
+        }
+    }
+}
+
+impl fmt::Display for Predicate {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fn bound<T>(value: &Option<T>, show: impl Fn(&T) -> String) -> String {
+            value.as_ref().map(show).unwrap_or_default()
+        }
+
+        match self {
+            Predicate::Date { attribute, from, to } => {
+                let field = match attribute.as_str() {
+                    "creationdate" => "created",
+                    "changedate" => "changed",
+                    _ => "used",
+                };
+                write!(f, "{}:{}..{}", field, bound(from, format_date), bound(to, format_date))
+            }
+            Predicate::Attribute { name, value } => match name.as_str() {
+                "creationid" => write!(f, "author:{}", value),
+                "changeid" => write!(f, "changer:{}", value),
+                "tuid" => write!(f, "tuid:{}", value),
+                _ => write!(f, "attr:{}={}", name, value),
+            },
+            Predicate::Prop { prop_type, value } => match prop_type.as_str() {
+                "tmgr:docname" => write!(f, "docname:{}", value),
+                "tmgr:context" => write!(f, "context:{}", value),
+                _ => write!(f, "prop:{}={}", prop_type, value),
+            },
+            Predicate::Lang(lang) => write!(f, "lang:{}", lang),
+            Predicate::Tuid { from, to } => write!(f, "tuid:{}..{}", bound(from, u64::to_string), bound(to, u64::to_string)),
+        }
+    }
+}
+
+#[derive(Clone, Debug, Default)]
+pub struct SelectOptions {
+    /// TUs must match these: all of them, or at least one with `any`. No predicates match every TU.
+    pub include: Vec<Predicate>,
+    /// TUs matching any of these are dropped.
+    pub exclude: Vec<Predicate>,
+    pub any: bool,
+}
+
+impl SelectOptions {
+    pub fn matches(&self, tu: &Tu) -> bool {
+        let included = if self.include.is_empty() {
+            true
+        } else if self.any {
+            self.include.iter().any(|predicate| predicate.matches(tu))
+        } else {
+            self.include.iter().all(|predicate| predicate.matches(tu))
+        };
+
+        included && !self.exclude.iter().any(|predicate| predicate.matches(tu))
+    }
+}
+
+/// Writes the TUs of `input` that match `options`; everything outside of TUs is copied.
+pub fn select(input: &str, output: &str, options: &SelectOptions) -> Result<()> {
+    let mut reader = TmxReader::new(open_input(input).context("Cannot open input file")?);
+
+    let mut writer = TmxWriter::new(create_output(output).context("Cannot create output file")?);
+
+    while let Some(item) = reader.read_item()? {
+        match item {
+            TmxItem::Tu(tu) if options.matches(&tu) => writer.write_tu(&tu)?,
+            TmxItem::Tu(_) => writer.drop_next_whitespace(),
+            TmxItem::Event(ev) => writer.write_event(ev)?,
+        }
+    }
+
+    writer.into_inner().finish()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pretty_assertions::assert_eq;
+
+    #[test]
+    fn test_parse_predicate() {
+        for s in [
+            "created:20170101T000000Z..",
+            "changed:..20200101T000000Z",
+            "author:manager",
+            "changer:Manager1",
+            "docname:docname2",
+            "context:context1",
+            "prop:tmgr:markup=OTMXUXLF",
+            "attr:usagecount=3",
+            "lang:en",
+            "tuid:2..4",
+            "tuid:7",
+        ] {
+            assert_eq!(s, s.parse::<Predicate>().unwrap().to_string());
+        }
+
+        assert_eq!("created:20170323T000000Z..20170324T000000Z", "created:2017-03-23..2017-03-24".parse::<Predicate>().unwrap().to_string());
+
+        for s in ["created:2017", "tuid:a..b", "prop:docname", "source:x", "lang:"] {
+            assert!(s.parse::<Predicate>().is_err(), "{}", s);
+        }
+    }
+
+    #[test]
+    fn test_select() {
+        let options = SelectOptions {
+            include: vec!["created:2017-01-01..".parse().unwrap(), "author:manager".parse().unwrap()],
+            exclude: vec!["context:context2".parse().unwrap()],
+            any: false,
+        };
+        select("test-data/select/test.tmx", "test-data/select/selected.tmx", &options).unwrap();
+
+        let expected = std::fs::read_to_string("test-data/select/expected.tmx").unwrap();
+        let output = std::fs::read_to_string("test-data/select/selected.tmx").unwrap();
+        std::fs::remove_file("test-data/select/selected.tmx").unwrap();
+
+        assert_eq!(expected, output);
+    }
+
+    #[test]
+    fn test_select_any() {
+        let options = SelectOptions {
+            include: vec!["docname:docname2".parse().unwrap(), "tuid:4..".parse().unwrap()],
+            any: true,
+            ..Default::default()
+        };
+        let tus: Vec<Tu> = TmxReader::new(open_input("test-data/select/test.tmx").unwrap())
+            .tus()
+            .collect::<Result<_>>()
+            .unwrap();
+        let selected: Vec<&str> = tus.iter().filter(|tu| options.matches(tu)).filter_map(|tu| tu.attribute("tuid")).collect();
+
+        assert_eq!(vec!["3", "4"], selected);
+    }
+}
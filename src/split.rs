@@ -0,0 +1,535 @@
+use anyhow::{Context, Result};
+use quick_xml::events::{BytesEnd, BytesText, Event};
+use std::collections::{HashMap, VecDeque};
+use std::fmt;
+use std::str::FromStr;
+
+use crate::element::Element;
+use crate::io::{append_output, create_output, open_input, Output};
+use crate::reader::{TmxItem, TmxReader};
+use crate::tu::Tu;
+use crate::whitespace::is_xml_whitespace;
+use crate::writer::TmxWriter;
+
+/// How the TUs are distributed over the output files.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum SplitBy {
+    /// `count:N`: at most N TUs per file.
+    Count(usize),
+    /// `size:SIZE`: files of about SIZE bytes, e.g. `size:50M`. A TU larger than SIZE gets a file of its own.
+    Size(u64),
+    /// `docname`: one file per `tmgr:docname`.
+    Docname,
+    /// `author`: one file per `creationid`, ignoring case.
+    Author,
+    /// `target-lang`: one file per target language, ignoring case, with the source and that target only.
+    TargetLang,
+}
+
+impl SplitBy {
+    fn is_grouped(&self) -> bool {
+        matches!(self, SplitBy::Docname | SplitBy::Author | SplitBy::TargetLang)
+    }
+}
+
+impl FromStr for SplitBy {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s.split_once(':') {
+            Some(("count", n)) => match n.trim().parse() {
+                Ok(0) | Err(_) => Err(anyhow::anyhow!("Invalid count '{}'. Expected a positive number", n)),
+                Ok(n) => Ok(SplitBy::Count(n)),
+            },
+            Some(("size", size)) => Ok(SplitBy::Size(parse_size(size)?)),
+            None if s == "docname" => Ok(SplitBy::Docname),
+            None if s == "author" => Ok(SplitBy::Author),
+            None if s == "target-lang" => Ok(SplitBy::TargetLang),
+            _ => Err(anyhow::anyhow!(
+                "Unknown split '{}'. Expected count:N, size:SIZE, docname, author or target-lang",
+                s
+            )),
+        }
+    }
+}
+
+impl fmt::Display for SplitBy {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SplitBy::Count(n) => write!(f, "count:{}", n),
+            SplitBy::Size(size) => write!(f, "size:{}", size),
+            SplitBy::Docname => write!(f, "docname"),
+            SplitBy::Author => write!(f, "author"),
+            SplitBy::TargetLang => write!(f, "target-lang"),
+        }
+    }
+}
+
+/// Parses `500000`, `500K`, `50M` or `1G` (also `50MB`, `50MiB`); units are powers of 1024.
+pub fn parse_size(s: &str) -> Result<u64> {
+    let upper = s.trim().to_ascii_uppercase();
+    let number = upper.trim_end_matches("IB").trim_end_matches('B');
+    let (digits, factor) = match number.char_indices().last() {
+        Some((i, 'K')) => (&number[..i], 1 << 10),
+        Some((i, 'M')) => (&number[..i], 1 << 20),
+        Some((i, 'G')) => (&number[..i], 1 << 30),
+        _ => (number, 1),
+    };
+
+    match digits.trim().parse::<u64>() {
+        Ok(n) if n > 0 => Ok(n * factor),
+        _ => Err(anyhow::anyhow!("Invalid size '{}'. Expected a number of bytes like 500K, 50M or 1G", s)),
+    }
+}
+
+/// The name of an output file: `{n}` is replaced with the file's number, from 1, and `{key}` with the
+/// docname, author or target language, with characters other than letters, digits, `-`, `_` and `.` as `_`.
+pub fn output_path(pattern: &str, n: usize, key: &str) -> String {
+    let key: String = key.chars()
+        .map(|c| if c.is_alphanumeric() || matches!(c, '-' | '_' | '.') { c } else { '_' })
+        .collect();
+    let key = if key.is_empty() { "none".to_string() } else { key };
+    pattern.replace("{n}", &n.to_string()).replace("{key}", &key)
+}
+
+/// An output file being written.
+struct Chunk {
+    path: String,
+    writer: TmxWriter<Output>,
+    tus: usize,
+    bytes: u64,
+}
+
+/// What every output file repeats: everything in front of the first TU and the closing tags.
+struct Frame {
+    prolog: Vec<Event<'static>>,
+    closing: Vec<Event<'static>>,
+}
+
+impl Frame {
+    fn open(&self, path: String) -> Result<Chunk> {
+        let mut writer = TmxWriter::new(create_output(&path).context(format!("Cannot create output file {}", path))?);
+        for ev in &self.prolog {
+            writer.write_event(ev.borrow())?;
+        }
+        Ok(Chunk { path, writer, tus: 0, bytes: 0 })
+    }
+
+    /// Opens the file of a group closed by [`Groups`] again, to write more TUs at its end.
+    fn reopen(&self, path: String, tus: usize) -> Result<Chunk> {
+        let writer = TmxWriter::new(append_output(&path).context(format!("Cannot reopen output file {}", path))?);
+        Ok(Chunk { path, writer, tus, bytes: 0 })
+    }
+
+    fn close(&self, chunk: Chunk) -> Result<(String, usize)> {
+        let mut writer = chunk.writer;
+        for ev in &self.closing {
+            writer.write_event(ev.borrow())?;
+        }
+        writer.into_inner().finish()?;
+        Ok((chunk.path, chunk.tus))
+    }
+
+    fn size(&self) -> u64 {
+        self.prolog.iter().chain(&self.closing).map(|ev| ev.len() as u64).sum()
+    }
+}
+
+/// The output files of a grouped split, by group. At most `max_open` of them are open at a time: the least recently
+/// written one is closed and reopened for appending when its group gets another TU.
+struct Groups {
+    open: HashMap<String, Chunk>,
+    // path and number of TUs of the groups whose file is closed for now
+    closed: HashMap<String, (String, usize)>,
+    // groups with an open file, least recently written first
+    recent: VecDeque<String>,
+    // every group, in the order their files were created
+    order: Vec<String>,
+    max_open: usize,
+}
+
+impl Groups {
+    fn new(max_open: usize) -> Self {
+        Groups { open: HashMap::new(), closed: HashMap::new(), recent: VecDeque::new(), order: Vec::new(), max_open }
+    }
+
+    fn contains(&self, group: &str) -> bool {
+        self.open.contains_key(group) || self.closed.contains_key(group)
+    }
+
+    /// The file of `group`, created at `path` for a new group.
+    fn get(&mut self, frame: &Frame, group: &str, path: impl FnOnce() -> String) -> Result<&mut Chunk> {
+        if self.open.contains_key(group) {
+            let i = self.recent.iter().position(|open| open == group).unwrap();
+            let group = self.recent.remove(i).unwrap();
+            self.recent.push_back(group);
+        } else {
+            if self.open.len() >= self.max_open
+                && let Some(oldest) = self.recent.pop_front()
+            {
+                let chunk = self.open.remove(&oldest).unwrap();
+                chunk.writer.into_inner().finish()?;
+                self.closed.insert(oldest, (chunk.path, chunk.tus));
+            }
+            let chunk = match self.closed.remove(group) {
+                Some((path, tus)) => frame.reopen(path, tus)?,
+                None => {
+                    self.order.push(group.to_string());
+                    frame.open(path())?
+                }
+            };
+            self.open.insert(group.to_string(), chunk);
+            self.recent.push_back(group.to_string());
+        }
+        Ok(self.open.get_mut(group).unwrap())
+    }
+
+    /// Closes every file, in the order they were created.
+    fn close(mut self, frame: &Frame) -> Result<Vec<(String, usize)>> {
+        let mut done = Vec::new();
+        for group in self.order {
+            let chunk = match self.closed.remove(&group) {
+                Some((path, tus)) => frame.reopen(path, tus)?,
+                None => self.open.remove(&group).unwrap(),
+            };
+            done.push(frame.close(chunk)?);
+        }
+        Ok(done)
+    }
+}
+
+/// How many output files a grouped split keeps open at a time.
+const MAX_OPEN_FILES: usize = 256;
+
+/// Splits `input` into TMX files named after `pattern`, each with the input's header.
+///
+/// The whitespace in front of each TU moves with it. Comments and the like between TUs go to the file of the TU
+/// before them, or with `docname`, `author` and `target-lang` to the file of the TU after them. Every file is closed
+/// like the input's `<body>` and `<tmx>` were opened, indented the same way and ending with a line break. Returns
+/// the path and number of TUs of every file, in the order they were created.
+pub fn split(input: &str, pattern: &str, by: &SplitBy) -> Result<Vec<(String, usize)>> {
+    split_with(input, pattern, by, MAX_OPEN_FILES)
+}
+
+fn split_with(input: &str, pattern: &str, by: &SplitBy, max_open: usize) -> Result<Vec<(String, usize)>> {
+    let needs_key = by.is_grouped();
+    let numbered = pattern.contains("{n}") || (needs_key && pattern.contains("{key}"));
+    if !numbered {
+        let expected = if needs_key { "{key} or {n}" } else { "{n}" };
+        return Err(anyhow::anyhow!("The output pattern '{}' must contain {}", pattern, expected));
+    }
+
+    let mut reader = TmxReader::new(open_input(input).context("Cannot open input file")?);
+
+    let mut frame = Frame { prolog: Vec::new(), closing: Vec::new() };
+    // open elements with the whitespace in front of them, to close them the same way
+    let mut open: Vec<(BytesEnd<'static>, Option<BytesText<'static>>)> = Vec::new();
+    let mut last_whitespace: Option<BytesText<'static>> = None;
+    // the whitespace in front of the next item, which moves with a TU
+    let mut pending: Option<Event<'static>> = None;
+
+    let mut source_lang: Option<String> = None;
+    let mut current: Option<Chunk> = None;
+    let mut groups = Groups::new(max_open);
+    let mut paths: HashMap<String, String> = HashMap::new();
+    let mut done: Vec<(String, usize)> = Vec::new();
+    // grouped: the events between TUs, with their whitespace, for the file of the next TU
+    let mut held: Vec<Event<'static>> = Vec::new();
+    let mut last_groups: Vec<String> = Vec::new();
+
+    while let Some(item) = reader.read_item()? {
+        let tu = match item {
+            TmxItem::Event(Event::Text(t)) if is_xml_whitespace(t.as_ref()) => {
+                if frame.closing.is_empty() {
+                    last_whitespace = Some(t.clone());
+                    frame.prolog.extend(pending.take());
+                }
+                pending = Some(Event::Text(t));
+                continue;
+            }
+            TmxItem::Event(ev) if frame.closing.is_empty() => {
+                if matches!(ev, Event::End(ref e) if e.name().as_ref() == b"body") {
+                    break;
+                }
+                match &ev {
+                    Event::Start(e) => {
+                        let end = BytesEnd::new(String::from_utf8_lossy(e.name().as_ref()).into_owned());
+                        open.push((end, last_whitespace.take()));
+                    }
+                    Event::End(_) => {
+                        open.pop();
+                    }
+                    _ => {}
+                }
+                last_whitespace = None;
+                frame.prolog.extend(pending.take());
+                frame.prolog.push(ev);
+                continue;
+            }
+            // after the first TU: comments and the like between TUs
+            TmxItem::Event(ev) => {
+                if matches!(ev, Event::End(ref e) if e.name().as_ref() == b"body") {
+                    break;
+                }
+                let ws = pending.take();
+                if needs_key {
+                    held.extend(ws);
+                    held.push(ev);
+                } else if let Some(chunk) = current.as_mut() {
+                    if let Some(ws) = ws {
+                        chunk.writer.write_event(ws)?;
+                    }
+                    chunk.writer.write_event(ev)?;
+                }
+                continue;
+            }
+            TmxItem::Tu(tu) => tu,
+        };
+
+        if frame.closing.is_empty() {
+            // the frame is complete with the first TU
+            let line_ending = match open.first().and_then(|(_, ws)| ws.as_ref()) {
+                Some(ws) if ws.windows(2).any(|w| w == b"\r\n") => "\r\n",
+                _ => "\n",
+            };
+            for (end, ws) in open.drain(..).rev() {
+                frame.closing.extend(ws.map(Event::Text));
+                frame.closing.push(Event::End(end));
+            }
+            frame.closing.push(Event::Text(BytesText::from_escaped(line_ending)));
+        }
+        if source_lang.is_none() {
+            source_lang = tu.tuvs.first().map(|tuv| tuv.lang.clone());
+        }
+
+        let ws = pending.take();
+        let bytes = tu.span.end - tu.span.start + ws.as_ref().map_or(0, |ws| ws.len() as u64);
+
+        if !needs_key {
+            let full = current.as_ref().is_some_and(|chunk| match by {
+                SplitBy::Count(n) => chunk.tus >= *n,
+                SplitBy::Size(size) => chunk.tus > 0 && frame.size() + chunk.bytes + bytes > *size,
+                _ => false,
+            });
+            if full {
+                done.push(frame.close(current.take().unwrap())?);
+            }
+            let chunk = match current.as_mut() {
+                Some(chunk) => chunk,
+                None => current.insert(frame.open(output_path(pattern, done.len() + 1, ""))?),
+            };
+            write_tu(chunk, ws, &tu, bytes)?;
+            continue;
+        }
+
+        let keyed: Vec<(String, Tu)> = match by {
+            SplitBy::Docname => vec![(tu.prop("tmgr:docname").unwrap_or_default().to_string(), tu)],
+            SplitBy::Author => vec![(tu.attribute("creationid").unwrap_or_default().to_string(), tu)],
+            _ => {
+                let mut targets: Vec<String> = Vec::new();
+                for tuv in &tu.tuvs {
+                    if Some(&tuv.lang) != source_lang.as_ref() && !targets.contains(&tuv.lang) {
+                        targets.push(tuv.lang.clone());
+                    }
+                }
+                if targets.is_empty() {
+                    vec![(String::new(), tu)]
+                } else {
+                    targets.iter()
+                        .map(|target| {
+                            let keep = |lang: &str| Some(lang) == source_lang.as_deref() || lang == target;
+                            (target.clone(), with_tuvs(&tu, keep))
+                        })
+                        .collect()
+                }
+            }
+        };
+
+        last_groups.clear();
+        for (key, tu) in keyed {
+            // user ids and languages are compared ignoring case, document names are not
+            let group = if *by == SplitBy::Docname { key.clone() } else { key.to_lowercase() };
+            let path = output_path(pattern, groups.order.len() + 1, &key);
+            if !groups.contains(&group) && let Some(other) = paths.insert(path.clone(), key.clone()) {
+                return Err(anyhow::anyhow!("'{}' and '{}' would both be written to {}. Use {{n}} in the output pattern", other, key, path));
+            }
+            let chunk = groups.get(&frame, &group, || path)?;
+            for ev in &held {
+                chunk.writer.write_event(ev.borrow())?;
+            }
+            write_tu(chunk, ws.clone(), &tu, bytes)?;
+            last_groups.push(group);
+        }
+        held.clear();
+    }
+
+    if frame.closing.is_empty() {
+        return Err(anyhow::anyhow!("No <tu> elements found in {}", input));
+    }
+
+    // what follows the last TU stays with it
+    for group in &last_groups {
+        let chunk = groups.get(&frame, group, String::new)?;
+        for ev in &held {
+            chunk.writer.write_event(ev.borrow())?;
+        }
+    }
+
+    if let Some(chunk) = current.take() {
+        done.push(frame.close(chunk)?);
+    }
+    done.extend(groups.close(&frame)?);
+    Ok(done)
+}
+
+fn write_tu(chunk: &mut Chunk, ws: Option<Event<'static>>, tu: &Tu, bytes: u64) -> Result<()> {
+    if let Some(ws) = ws {
+        chunk.writer.write_event(ws)?;
+    }
+    chunk.writer.write_tu(tu)?;
+    chunk.tus += 1;
+    chunk.bytes += bytes;
+    Ok(())
+}
+
+/// A copy of `tu` with only the `<tuv>` elements whose language passes `keep`, dropping the whitespace in front of
+/// the others.
+fn with_tuvs(tu: &Tu, keep: impl Fn(&str) -> bool) -> Tu {
+    let mut events: Vec<Event<'static>> = Vec::with_capacity(tu.events.len());
+    let mut depth = 0;
+    let mut tuv_index = 0;
+    let mut skipping = false;
+
+    for ev in &tu.events {
+        match ev {
+            Event::Start(e) | Event::Empty(e) if depth == 1 && e.name().as_ref() == b"tuv" => {
+                skipping = !tu.tuvs.get(tuv_index).is_some_and(|tuv| keep(&tuv.lang));
+                tuv_index += 1;
+                if skipping && matches!(events.last(), Some(Event::Text(t)) if is_xml_whitespace(t.as_ref())) {
+                    events.pop();
+                }
+            }
+            _ => {}
+        }
+        match ev {
+            Event::Start(_) => depth += 1,
+            Event::End(_) => depth -= 1,
+            _ => {}
+        }
+        if !skipping {
+            events.push(ev.clone());
+        }
+        // the skipped <tuv> ends with its own end tag, or is a single empty element
+        if skipping && depth == 1 && matches!(ev, Event::End(_) | Event::Empty(_)) {
+            skipping = false;
+        }
+    }
+
+    let mut filtered = Tu::from_events(events);
+    filtered.span = tu.span.clone();
+    filtered.namespaces = tu.namespaces.clone();
+    filtered
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pretty_assertions::assert_eq;
+    use std::io::Read;
+
+    #[test]
+    fn test_parse_split() {
+        assert_eq!(SplitBy::Count(100), "count:100".parse().unwrap());
+        assert_eq!(SplitBy::Size(50 << 20), "size:50M".parse().unwrap());
+        assert_eq!(SplitBy::Size(2 << 30), "size:2GiB".parse().unwrap());
+        assert_eq!(SplitBy::Size(1500), "size:1500".parse().unwrap());
+        assert_eq!(SplitBy::TargetLang, "target-lang".parse().unwrap());
+        assert!("count:0".parse::<SplitBy>().is_err());
+        assert!("size:lots".parse::<SplitBy>().is_err());
+        assert!("lang".parse::<SplitBy>().is_err());
+        assert_eq!("part-3-en_US.tmx", output_path("part-{n}-{key}.tmx", 3, "en US"));
+    }
+
+    fn read_outputs(outputs: &[(String, usize)]) -> Vec<(String, usize, String)> {
+        outputs.iter()
+            .map(|(path, tus)| {
+                let content = std::fs::read_to_string(path).unwrap();
+                std::fs::remove_file(path).unwrap();
+                (path.clone(), *tus, content)
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_split_count() {
+        let outputs = split("test-data/split/test.tmx", "test-data/split/count_{n}.tmx", &SplitBy::Count(2)).unwrap();
+        let outputs = read_outputs(&outputs);
+
+        assert_eq!(
+            vec![("test-data/split/count_1.tmx", 2), ("test-data/split/count_2.tmx", 1)],
+            outputs.iter().map(|(path, tus, _)| (path.as_str(), *tus)).collect::<Vec<_>>()
+        );
+        assert_eq!(std::fs::read_to_string("test-data/split/expected_count_1.tmx").unwrap(), outputs[0].2);
+        assert_eq!(std::fs::read_to_string("test-data/split/expected_count_2.tmx").unwrap(), outputs[1].2);
+    }
+
+    #[test]
+    fn test_split_size() {
+        let outputs = split("test-data/split/test.tmx", "test-data/split/size_{n}.tmx", &SplitBy::Size(1)).unwrap();
+        let outputs = read_outputs(&outputs);
+
+        // every TU is larger than the limit, so each gets a file of its own
+        assert_eq!(vec![1, 1, 1], outputs.iter().map(|(_, tus, _)| *tus).collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn test_split_target_lang() {
+        let outputs = split("test-data/split/test.tmx", "test-data/split/lang_{key}.tmx", &SplitBy::TargetLang).unwrap();
+        let outputs = read_outputs(&outputs);
+
+        assert_eq!(
+            vec![("test-data/split/lang_en-US.tmx", 3), ("test-data/split/lang_fr.tmx", 1)],
+            outputs.iter().map(|(path, tus, _)| (path.as_str(), *tus)).collect::<Vec<_>>()
+        );
+        assert_eq!(std::fs::read_to_string("test-data/split/expected_lang_fr.tmx").unwrap(), outputs[1].2);
+    }
+
+    #[test]
+    fn test_split_author() {
+        let outputs = split("test-data/split/test.tmx", "test-data/split/author_{key}.tmx", &SplitBy::Author).unwrap();
+        let outputs = read_outputs(&outputs);
+
+        assert_eq!(
+            vec![("test-data/split/author_manager.tmx", 2), ("test-data/split/author_Translator.tmx", 1)],
+            outputs.iter().map(|(path, tus, _)| (path.as_str(), *tus)).collect::<Vec<_>>()
+        );
+    }
+
+    #[test]
+    fn test_split_docname_reopens_files() {
+        // one open file at a time: every change of docname closes a file and reopens the other one
+        for extension in ["tmx", "tmx.gz"] {
+            let pattern = format!("test-data/split/docname_{{key}}.{}", extension);
+            let outputs = split_with("test-data/split/comments.tmx", &pattern, &SplitBy::Docname, 1).unwrap();
+
+            let mut contents = Vec::new();
+            for (path, tus) in &outputs {
+                let mut content = String::new();
+                open_input(path).unwrap().read_to_string(&mut content).unwrap();
+                std::fs::remove_file(path).unwrap();
+                contents.push((*tus, content));
+            }
+
+            // comments go with the TU after them, the last one with the last TU
+            assert_eq!(
+                vec![
+                    (2, std::fs::read_to_string("test-data/split/expected_docname_manual.tmx").unwrap()),
+                    (1, std::fs::read_to_string("test-data/split/expected_docname_guide.tmx").unwrap()),
+                ],
+                contents,
+                "{}",
+                extension
+            );
+        }
+    }
+}
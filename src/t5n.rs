@@ -0,0 +1,322 @@
+use anyhow::{Context, Result};
+use quick_xml::escape::partial_escape;
+use quick_xml::events::{BytesEnd, BytesStart, BytesText, Event};
+use regex::Regex;
+use std::collections::HashMap;
+use std::fmt;
+use std::str::FromStr;
+use std::sync::LazyLock;
+
+use crate::io::{create_output, open_input};
+use crate::namespace::{Namespaces, PlaceholderHandler};
+use crate::reader::{TmxItem, TmxReader};
+use crate::tu::{event_text, Tu};
+use crate::writer::TmxWriter;
+
+/// Numbers like `4`, `1.5` or `10,000` and variables like `{name}`, `{{name}}`, `%s` or `%1$d`.
+pub const DEFAULT_PROTECT_PATTERN: &str = r"\{\{?\w+\}?\}|%(?:\d+\$)?[sd]|\d+(?:[.,]\d+)*";
+
+static DEFAULT_PROTECT: LazyLock<Regex> = LazyLock::new(|| Regex::new(DEFAULT_PROTECT_PATTERN).unwrap());
+
+/// What [`convert`] does with the `<t5:n>` placeholders of t5memory.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum T5nMode {
+    /// Replace every `<t5:n>` with its `n` value.
+    N,
+    /// Replace every `<t5:n>` with its `r` attribute, base64-decoded.
+    R,
+    /// Replace every `<t5:n>` with a TMX `<ph>` that holds the placeholder as native code.
+    Ph,
+    /// Replace the numbers and variables of [`DEFAULT_PROTECT_PATTERN`] in segments with `<t5:n>` placeholders.
+    Protect,
+}
+
+impl FromStr for T5nMode {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "n" => Ok(T5nMode::N),
+            "r" => Ok(T5nMode::R),
+            "ph" => Ok(T5nMode::Ph),
+            "protect" => Ok(T5nMode::Protect),
+            _ => Err(anyhow::anyhow!("Unknown t5n mode '{}'. Expected n, r, ph or protect", s)),
+        }
+    }
+}
+
+impl fmt::Display for T5nMode {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            T5nMode::N => write!(f, "n"),
+            T5nMode::R => write!(f, "r"),
+            T5nMode::Ph => write!(f, "ph"),
+            T5nMode::Protect => write!(f, "protect"),
+        }
+    }
+}
+
+/// Applies `mode` to `tu`, protecting with [`DEFAULT_PROTECT_PATTERN`]. `namespaces` tells which elements are
+/// placeholders; the unprotecting modes read their value, rule and id with its handlers. Returns the number of
+/// placeholders replaced or created.
+pub fn convert(tu: &mut Tu, mode: T5nMode, namespaces: &Namespaces) -> usize {
+    match mode {
+        T5nMode::Protect => protect(tu, &DEFAULT_PROTECT, namespaces),
+        _ => replace_placeholders(tu, mode, namespaces),
+    }
+}
+
+// placeholders without the attribute a mode needs, or with a rule that is not base64 of UTF-8, are kept
+fn replace_placeholders(tu: &mut Tu, mode: T5nMode, namespaces: &Namespaces) -> usize {
+    let mut count = 0;
+    let mut events = Vec::with_capacity(tu.events.len());
+    for (ev, placeholder) in tu.events.iter().zip(namespaces.placeholders(tu)) {
+        let replacement = match (ev, placeholder) {
+            (Event::Empty(e), Some(handler)) => placeholder_replacement(e, mode, handler),
+            _ => None,
+        };
+        match replacement {
+            Some(replacement) => {
+                count += 1;
+                events.extend(replacement);
+            }
+            None => events.push(ev.clone()),
+        }
+    }
+
+    if count > 0 {
+        tu.set_events(events);
+    }
+    count
+}
+
+fn placeholder_replacement(e: &BytesStart, mode: T5nMode, handler: &PlaceholderHandler) -> Option<Vec<Event<'static>>> {
+    match mode {
+        T5nMode::N => Some(vec![Event::Text(BytesText::new(&handler.value_of(e)?).into_owned())]),
+        T5nMode::R => {
+            let rule = base64_decode(&handler.rule_of(e)?)?;
+            Some(vec![Event::Text(BytesText::new(&String::from_utf8(rule).ok()?).into_owned())])
+        }
+        T5nMode::Ph => {
+            let mut ph = BytesStart::new("ph");
+            if let Some(id) = handler.id_of(e).filter(|id| id.parse::<u32>().is_ok()) {
+                ph.push_attribute(("x", id.as_str()));
+            }
+            ph.push_attribute(("type", String::from_utf8_lossy(e.name().as_ref()).as_ref()));
+            let native = format!("<{}/>", String::from_utf8_lossy(e));
+            Some(vec![
+                Event::Start(ph),
+                Event::Text(BytesText::from_escaped(partial_escape(&native).into_owned())),
+                Event::End(BytesEnd::new("ph")),
+            ])
+        }
+        T5nMode::Protect => None,
+    }
+}
+
+/// Replaces every match of `pattern` in the text directly inside `<seg>` with a `<t5:n>` placeholder: `n` is the
+/// matched text and `r` the base64-encoded pattern. Equal values get the same `id` in every `<tuv>`, numbered after
+/// the highest `id` of the placeholders in `namespaces` already in the TU. Returns the number of placeholders created.
+pub fn protect(tu: &mut Tu, pattern: &Regex, namespaces: &Namespaces) -> usize {
+    let mut protector = Protector {
+        pattern,
+        rule: base64_encode(pattern.as_str().as_bytes()),
+        ids: HashMap::new(),
+        next_id: max_id(tu, namespaces) + 1,
+        count: 0,
+    };
+
+    let mut events = Vec::with_capacity(tu.events.len());
+    let mut run: Vec<Event<'static>> = Vec::new();
+    // element names from <tu> down, innermost last
+    let mut path: Vec<Vec<u8>> = Vec::new();
+
+    for ev in std::mem::take(&mut tu.events) {
+        let is_text = matches!(ev, Event::Text(_) | Event::CData(_) | Event::GeneralRef(_));
+        if is_text && path.len() == 3 && path[1] == b"tuv" && path[2] == b"seg" {
+            run.push(ev);
+            continue;
+        }
+
+        protector.protect_run(&mut run, &mut events);
+        match &ev {
+            Event::Start(e) => path.push(e.name().as_ref().to_vec()),
+            Event::End(_) => {
+                path.pop();
+            }
+            _ => {}
+        }
+        events.push(ev);
+    }
+    protector.protect_run(&mut run, &mut events);
+
+    if protector.count > 0 {
+        tu.set_events(events);
+    } else {
+        tu.events = events;
+    }
+    protector.count
+}
+
+struct Protector<'a> {
+    pattern: &'a Regex,
+    rule: String,
+    // id of every value protected so far
+    ids: HashMap<String, u32>,
+    next_id: u32,
+    count: usize,
+}
+
+impl Protector<'_> {
+    // a run of text and references is rewritten only when the pattern matches in it
+    fn protect_run(&mut self, run: &mut Vec<Event<'static>>, events: &mut Vec<Event<'static>>) {
+        if run.is_empty() {
+            return;
+        }
+
+        let text: String = run.iter().filter_map(event_text).collect();
+        let matches: Vec<_> = self.pattern.find_iter(&text).filter(|m| !m.is_empty()).collect();
+        if matches.is_empty() {
+            events.append(run);
+            return;
+        }
+
+        let mut last = 0;
+        for m in matches {
+            if m.start() > last {
+                events.push(Event::Text(BytesText::new(&text[last..m.start()]).into_owned()));
+            }
+
+            let id = match self.ids.get(m.as_str()) {
+                Some(id) => *id,
+                None => {
+                    let id = self.next_id;
+                    self.next_id += 1;
+                    self.ids.insert(m.as_str().to_string(), id);
+                    id
+                }
+            };
+            let mut placeholder = BytesStart::new("t5:n");
+            placeholder.push_attribute(("id", id.to_string().as_str()));
+            placeholder.push_attribute(("r", self.rule.as_str()));
+            placeholder.push_attribute(("n", m.as_str()));
+            events.push(Event::Empty(placeholder));
+
+            self.count += 1;
+            last = m.end();
+        }
+
+        if last < text.len() {
+            events.push(Event::Text(BytesText::new(&text[last..]).into_owned()));
+        }
+        run.clear();
+    }
+}
+
+fn max_id(tu: &Tu, namespaces: &Namespaces) -> u32 {
+    tu.events.iter().zip(namespaces.placeholders(tu))
+        .filter_map(|(ev, placeholder)| match (ev, placeholder) {
+            (Event::Empty(e), Some(handler)) => handler.id_of(e).and_then(|id| id.parse().ok()),
+            _ => None,
+        })
+        .max()
+        .unwrap_or(0)
+}
+
+const BASE64: &[u8; 64] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+
+fn base64_encode(bytes: &[u8]) -> String {
+    let mut encoded = String::with_capacity(bytes.len().div_ceil(3) * 4);
+    for chunk in bytes.chunks(3) {
+        let word = chunk.iter().enumerate().fold(0u32, |word, (i, byte)| word | (*byte as u32) << (16 - 8 * i));
+        for i in 0..4 {
+            if i <= chunk.len() {
+                encoded.push(BASE64[(word >> (18 - 6 * i) & 0x3f) as usize] as char);
+            } else {
+                encoded.push('=');
+            }
+        }
+    }
+    encoded
+}
+
+// `None` when `text` is not base64
+pub(crate) fn base64_decode(text: &str) -> Option<Vec<u8>> {
+    let text = text.trim_end_matches('=').as_bytes();
+    let mut decoded = Vec::with_capacity(text.len() * 3 / 4);
+    for chunk in text.chunks(4) {
+        if chunk.len() == 1 {
+            return None;
+        }
+        let mut word = 0u32;
+        for (i, c) in chunk.iter().enumerate() {
+            let value = BASE64.iter().position(|b| b == c)? as u32;
+            word |= value << (18 - 6 * i);
+        }
+        decoded.extend(word.to_be_bytes()[1..chunk.len()].iter());
+    }
+    Some(decoded)
+}
+
+#[derive(Clone, Debug)]
+pub struct T5nOptions {
+    pub mode: T5nMode,
+    /// What `protect` replaces instead of [`DEFAULT_PROTECT_PATTERN`].
+    pub pattern: Option<Regex>,
+    /// Which inline elements are placeholders.
+    pub namespaces: Namespaces,
+}
+
+/// Applies `options` to every TU of `input`. Returns the number of placeholders replaced or created.
+pub fn t5n(input: &str, output: &str, options: &T5nOptions) -> Result<usize> {
+    let mut reader = TmxReader::new(open_input(input).context("Cannot open input file")?);
+
+    let mut writer = TmxWriter::new(create_output(output).context("Cannot create output file")?);
+
+    let mut count = 0;
+    while let Some(item) = reader.read_item()? {
+        match item {
+            TmxItem::Tu(mut tu) => {
+                count += match (options.mode, &options.pattern) {
+                    (T5nMode::Protect, Some(pattern)) => protect(&mut tu, pattern, &options.namespaces),
+                    (mode, _) => convert(&mut tu, mode, &options.namespaces),
+                };
+                writer.write_tu(&tu)?;
+            }
+            TmxItem::Event(ev) => writer.write_event(ev)?,
+        }
+    }
+
+    writer.into_inner().finish()?;
+    Ok(count)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pretty_assertions::assert_eq;
+
+    #[test]
+    fn test_base64() {
+        for text in ["", "x", "de", "default Y-m-d"] {
+            assert_eq!(base64_decode(&base64_encode(text.as_bytes())).unwrap(), text.as_bytes());
+        }
+        assert_eq!(base64_encode(b"default Y-m-d"), "ZGVmYXVsdCBZLW0tZA==");
+        assert_eq!(base64_decode("eA=="), Some(b"x".to_vec()));
+        assert_eq!(base64_decode("e!=="), None);
+    }
+
+    #[test]
+    fn test_t5n() {
+        for mode in ["n", "r", "ph", "protect"] {
+            let output = format!("test-data/t5n/converted_{}.tmx", mode);
+            let options = T5nOptions { mode: mode.parse().unwrap(), pattern: None, namespaces: Namespaces::default() };
+            t5n("test-data/t5n/test.tmx", &output, &options).unwrap();
+
+            let expected = std::fs::read_to_string(format!("test-data/t5n/expected_{}.tmx", mode)).unwrap();
+            let converted = std::fs::read_to_string(&output).unwrap();
+            std::fs::remove_file(&output).unwrap();
+            assert_eq!(converted, expected, "mode {}", mode);
+        }
+    }
+}
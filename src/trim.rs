@@ -1,91 +1,135 @@
 use anyhow::{Context, Result};
 use quick_xml::events::Event;
-use quick_xml::reader::Reader;
-use quick_xml::Writer;
-use std::fs::File;
-use std::io::{BufReader, BufWriter};
-
+use std::collections::VecDeque;
+
+use crate::io::{create_output, open_input};
+use crate::namespace::Namespaces;
+use crate::quarantine::Quarantine;
+use crate::reader::{TmxItem, TmxReader};
+use crate::t5n::{convert, T5nMode};
+use crate::tu::Tu;
 use crate::whitespace::is_xml_whitespace;
+use crate::writer::TmxWriter;
+
+/// Which TUs `trim` keeps. TUs are counted from 0 in file order; the options apply in field order.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct TrimOptions {
+    /// Drop TUs 0 to `skip - 1`.
+    pub skip: usize,
+    /// Of the TUs after the skipped ones, keep only the first `take`.
+    pub take: Option<usize>,
+    /// Of those, keep the first one of every `every`: TUs `skip`, `skip + every`, `skip + 2 * every`, ...
+    /// 0 and 1 keep all of them.
+    pub every: usize,
+    /// Of what is left, keep only the last `tail`.
+    pub tail: Option<usize>,
+    /// Convert the `<t5:n>` placeholders of the TUs written, like the `t5n` command.
+    pub t5n: Option<T5nMode>,
+    /// Which inline elements are placeholders for `t5n`.
+    pub namespaces: Namespaces,
+    /// Put malformed TUs there and go on instead of failing.
+    pub quarantine: Option<Quarantine>,
+}
 
-pub fn trim(input: &String, output: &String, n: usize) -> Result<()> {
-    // Usage: tmx_trimmer <input.tmx> <output.tmx> <N>
-    let infile = File::open(input).context("Cannot open input file")?;
-    let mut reader = Reader::from_reader(BufReader::new(infile));
-    // Keep whitespace as-is globally; we’ll selectively drop only the ws after skipped <tu>.
-    reader.config_mut().trim_text(false);
-
-    let outfile = File::create(output).context("Cannot create output file")?;
-    let mut writer = Writer::new(BufWriter::new(outfile));
-
-    let mut buf = Vec::new();
-    let mut skip_count = 0usize;
-    let mut inside_tu = false;
-    let mut just_skipped_tu = false;
-
-
-    loop {
-        match reader.read_event_into(&mut buf) {
-            Ok(Event::Eof) => break,
-
-            Ok(Event::Start(ref e)) if e.name().as_ref() == b"tu" => {
-                inside_tu = true;
-                skip_count += 1;
-                if skip_count >= n {
-                    writer.write_event(Event::Start(e.clone()))?;
-                    just_skipped_tu = false;
-                } else {
-                    just_skipped_tu = true;
-                }
+impl TrimOptions {
+    /// Sets `skip` and `take` from a range `from..to` of 0-based TU numbers, `to` excluded; either end may be left out.
+    pub fn with_range(mut self, range: &str) -> Result<Self> {
+        let (from, to) = range.split_once("..")
+            .ok_or_else(|| anyhow::anyhow!("Expected a range like 1000..2000, got '{}'", range))?;
+        let bound = |value: &str| -> Result<Option<usize>> {
+            match value.trim() {
+                "" => Ok(None),
+                value => value.parse().map(Some).context(format!("Invalid TU number '{}' in range {}", value, range)),
             }
+        };
 
-            Ok(Event::Empty(ref e)) if e.name().as_ref() == b"tu" => {
-                skip_count += 1;
-                if skip_count >= n {
-                    writer.write_event(Event::Empty(e.clone()))?;
-                    just_skipped_tu = false;
-                } else {
-                    just_skipped_tu = true;
-                }
-            }
+        let (from, to) = (bound(from)?.unwrap_or(0), bound(to)?);
+        if to.is_some_and(|to| to < from) {
+            return Err(anyhow::anyhow!("Empty range {}: the end comes before the start", range));
+        }
+        self.skip = from;
+        self.take = to.map(|to| to - from);
+        Ok(self)
+    }
 
-            Ok(Event::End(ref e)) if e.name().as_ref() == b"tu" => {
-                if skip_count >= n {
-                    writer.write_event(Event::End(e.clone()))?;
-                    just_skipped_tu = false;
-                } else {
-                    just_skipped_tu = true;
+    /// `true` when the TU numbered `index` passes `skip`, `take` and `every`.
+    fn keeps(&self, index: usize) -> bool {
+        index >= self.skip
+            && self.take.is_none_or(|take| index - self.skip < take)
+            && (self.every <= 1 || (index - self.skip).is_multiple_of(self.every))
+    }
+}
+
+/// Writes the TUs of `input` selected by `options`; everything outside of TUs is copied.
+///
+/// Whitespace is written together with the item that follows it, so the indentation in front of a dropped TU is
+/// dropped with it. Returns the number of TUs written.
+pub fn trim(input: &str, output: &str, options: &TrimOptions) -> Result<usize> {
+    let mut reader = TmxReader::new(open_input(input).context("Cannot open input file")?)
+        .with_quarantine(options.quarantine.as_ref(), input);
+
+    let mut writer = TmxWriter::new(create_output(output).context("Cannot create output file")?);
+
+    let mut index = 0usize;
+    let mut pending: Option<Event<'static>> = None;
+    // the TUs kept so far, with the whitespace in front of them, when only the last ones are written
+    let mut tail: VecDeque<(Option<Event<'static>>, Tu)> = VecDeque::new();
+    let mut written = 0;
+
+    while let Some(item) = reader.read_item()? {
+        match item {
+            TmxItem::Event(Event::Text(t)) if is_xml_whitespace(t.as_ref()) => {
+                if let Some(ws) = pending.replace(Event::Text(t)) {
+                    writer.write_event(ws)?;
                 }
-                inside_tu = false;
             }
-
-            Ok(Event::Text(ref t)) => {
-                // If we just skipped a <tu>, and this is whitespace, do not write it
-                if just_skipped_tu && is_xml_whitespace(t.as_ref()) {
-                    // skip writing this whitespace
-                    just_skipped_tu = false;
-                } else if !(inside_tu && skip_count < n) {
-                    writer.write_event(Event::Text(t.clone()))?;
-                    just_skipped_tu = false;
+            TmxItem::Tu(mut tu) => {
+                let ws = pending.take();
+                if options.keeps(index) {
+                    if let Some(mode) = options.t5n {
+                        convert(&mut tu, mode, &options.namespaces);
+                    }
+                    match options.tail {
+                        Some(n) => {
+                            tail.push_back((ws, tu));
+                            if tail.len() > n {
+                                tail.pop_front();
+                            }
+                        }
+                        None => {
+                            if let Some(ws) = ws {
+                                writer.write_event(ws)?;
+                            }
+                            writer.write_tu(&tu)?;
+                            written += 1;
+                        }
+                    }
                 }
+                index += 1;
             }
-
-            Ok(ev) => {
-                if !(inside_tu && skip_count < n) {
-                    writer.write_event(ev.clone())?;
-                    just_skipped_tu = false;
+            TmxItem::Event(ev) => {
+                if matches!(ev, Event::End(ref e) if e.name().as_ref() == b"body") {
+                    for (ws, tu) in tail.drain(..) {
+                        if let Some(ws) = ws {
+                            writer.write_event(ws)?;
+                        }
+                        writer.write_tu(&tu)?;
+                        written += 1;
+                    }
+                }
+                if let Some(ws) = pending.take() {
+                    writer.write_event(ws)?;
                 }
+                writer.write_event(ev)?;
             }
-
-            Err(e) => return Err(anyhow::anyhow!(
-                "XML parse error at {:?}: {}",
-                reader.error_position(),
-                e
-            )),
         }
-        buf.clear();
+    }
+    if let Some(ws) = pending.take() {
+        writer.write_event(ws)?;
     }
 
-    Ok(())
+    writer.into_inner().finish()?;
+    Ok(written)
 }
 
 // test
@@ -93,12 +137,14 @@ pub fn trim(input: &String, output: &String, n: usize) -> Result<()> {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::element::Element;
     // use pretty_assertions::{assert_eq};
     
     #[test]
     fn test_filter_no_skip_keep() {
-        let result = trim(&"test-data/trim/test.tmx".to_string(), &"test-data/trim/trimed.tmx".to_string(), 5);
-        assert!(result.is_ok());
+        // skips tuids 1 to 4
+        let result = trim("test-data/trim/test.tmx", "test-data/trim/trimed.tmx", &TrimOptions { skip: 4, ..Default::default() });
+        assert_eq!(3, result.unwrap());
 
         let expected = std::fs::read_to_string("test-data/trim/expected.tmx").unwrap();
         let output = std::fs::read_to_string("test-data/trim/trimed.tmx").unwrap();
@@ -108,4 +154,48 @@ mod tests {
 
         assert_eq!(expected, output);
     }
+
+    fn tuids(options: &TrimOptions) -> (Vec<String>, String) {
+        let dir = tempfile::tempdir().unwrap();
+        let output = dir.path().join("trimmed.tmx").to_string_lossy().to_string();
+        trim("test-data/trim/test.tmx", &output, options).unwrap();
+
+        let content = std::fs::read_to_string(&output).unwrap();
+        let tuids = TmxReader::new(content.as_bytes()).tus()
+            .map(|tu| tu.unwrap().attribute("tuid").unwrap().to_string())
+            .collect();
+        (tuids, content)
+    }
+
+    #[test]
+    fn test_trim_options() {
+        let options = |range: &str| TrimOptions::default().with_range(range).unwrap();
+
+        assert_eq!(vec!["3", "4"], tuids(&options("2..4")).0);
+        assert_eq!(vec!["1", "2"], tuids(&options("..2")).0);
+        assert_eq!(vec!["6", "7"], tuids(&options("5..")).0);
+        assert_eq!(vec!["2", "5"], tuids(&TrimOptions { skip: 1, take: Some(5), every: 3, tail: None, t5n: None, namespaces: Namespaces::default(), quarantine: None }).0);
+        assert_eq!(vec!["6", "7"], tuids(&TrimOptions { tail: Some(2), ..Default::default() }).0);
+        assert_eq!(vec!["3", "5", "7"], tuids(&TrimOptions { skip: 2, every: 2, tail: Some(5), ..Default::default() }).0);
+        assert!(TrimOptions::default().with_range("4..2").is_err());
+        assert!(TrimOptions::default().with_range("4").is_err());
+
+        // the indentation of dropped TUs goes with them
+        let (_, content) = tuids(&TrimOptions { take: Some(1), ..Default::default() });
+        assert!(content.contains("</tu>\n    </body>"), "{}", content);
+    }
+
+    #[test]
+    fn test_trim_quarantine() {
+        let dir = tempfile::tempdir().unwrap();
+        let output = dir.path().join("trimmed.tmx").to_string_lossy().to_string();
+        let quarantine = Quarantine::create(&dir.path().join("quarantine.xml").to_string_lossy()).unwrap();
+
+        let options = TrimOptions { quarantine: Some(quarantine.clone()), ..Default::default() };
+        assert_eq!(3, trim("test-data/trim/malformed.tmx", &output, &options).unwrap());
+        assert_eq!(3, quarantine.finish().unwrap());
+
+        let expected = std::fs::read_to_string("test-data/trim/expected_malformed.tmx").unwrap();
+        assert_eq!(expected, std::fs::read_to_string(&output).unwrap());
+    }
 }
@@ -0,0 +1,258 @@
+use std::borrow::Cow;
+use std::cell::RefCell;
+use std::ops::Range;
+
+use quick_xml::escape::resolve_predefined_entity;
+use quick_xml::events::{BytesStart, Event};
+
+use crate::element::{Children, Element};
+use crate::whitespace::is_xml_whitespace;
+
+/// A `<prop type="...">value</prop>` child of a `<tu>`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Prop {
+    pub prop_type: String,
+    pub value: String,
+}
+
+/// A `<tuv>` with its language and the events between `<seg>` and `</seg>`.
+#[derive(Clone, Debug)]
+pub struct Tuv {
+    pub lang: String,
+    pub seg: Vec<Event<'static>>,
+}
+
+impl Tuv {
+    /// Plain text of the segment. Inline elements are dropped, references are resolved.
+    pub fn seg_text(&self) -> String {
+        let mut text = String::new();
+        for ev in &self.seg {
+            if let Some(t) = event_text(ev) {
+                text.push_str(&t);
+            }
+        }
+        text
+    }
+
+    /// `true` when the `<tuv>` is in `lang`, ignoring case. `en` also matches `en-US`.
+    pub fn has_lang(&self, lang: &str) -> bool {
+        self.lang.eq_ignore_ascii_case(lang)
+            || (self.lang.get(..lang.len()).is_some_and(|prefix| prefix.eq_ignore_ascii_case(lang))
+                && self.lang.as_bytes().get(lang.len()) == Some(&b'-'))
+    }
+
+    /// Text of the segment with inline elements written as XML, e.g. `4 <t5:n id="2" n="10"/> items`.
+    pub fn seg_markup(&self) -> String {
+        let mut markup = String::new();
+        for ev in &self.seg {
+            match ev {
+                Event::Start(e) => markup.push_str(&format!("<{}>", String::from_utf8_lossy(e))),
+                Event::Empty(e) => markup.push_str(&format!("<{}/>", String::from_utf8_lossy(e))),
+                Event::End(e) => markup.push_str(&format!("</{}>", String::from_utf8_lossy(e.name().as_ref()))),
+                _ => {
+                    if let Some(t) = event_text(ev) {
+                        markup.push_str(&t);
+                    }
+                }
+            }
+        }
+        markup
+    }
+}
+
+/// One translation unit: the typed fields plus every raw event from `<tu>` to `</tu>`.
+#[derive(Clone, Debug)]
+pub struct Tu {
+    pub attributes: Vec<(String, String)>,
+    pub props: Vec<Prop>,
+    pub notes: Vec<String>,
+    pub tuvs: Vec<Tuv>,
+    pub events: Vec<Event<'static>>,
+    /// Byte range of the `<tu>` element in the input, when read by [`crate::reader::TmxReader`].
+    pub span: Range<u64>,
+    /// Namespace bindings in scope at the `<tu>` element, its own included, as (prefix, URI) pairs;
+    /// the default namespace has an empty prefix. Filled in by [`crate::reader::TmxReader`].
+    pub namespaces: Vec<(String, String)>,
+    /// Malformed dates already reported by [`crate::date::tu_date`], as (attribute, value) pairs.
+    pub(crate) reported_dates: RefCell<Vec<(String, String)>>,
+}
+
+impl Tu {
+    /// Builds the typed view from the raw events of a `<tu>` element.
+    pub fn from_events(events: Vec<Event<'static>>) -> Tu {
+        let mut tu = Tu {
+            attributes: Vec::new(),
+            props: Vec::new(),
+            notes: Vec::new(),
+            tuvs: Vec::new(),
+            events: Vec::new(),
+            span: 0..0,
+            namespaces: Vec::new(),
+            reported_dates: RefCell::default(),
+        };
+
+        // element names below <tu>, innermost last
+        let mut path: Vec<Vec<u8>> = Vec::new();
+        let mut children = Children::default();
+
+        for ev in &events {
+            match ev {
+                Event::Start(e) | Event::Empty(e) if e.name().as_ref() == b"tu" => {
+                    tu.attributes = attributes(e);
+                }
+                Event::End(e) if e.name().as_ref() == b"tu" => {}
+
+                Event::Start(e) => {
+                    let name = e.name().as_ref().to_vec();
+                    match (path.as_slice(), name.as_slice()) {
+                        ([], b"prop" | b"note") => children.start(e),
+                        ([], b"tuv") => tu.tuvs.push(Tuv { lang: tuv_lang(e), seg: Vec::new() }),
+                        ([tuv], b"seg") if tuv.as_slice() == b"tuv" => {}
+                        _ => push_seg_event(&mut tu.tuvs, &path, ev),
+                    }
+                    path.push(name);
+                }
+                Event::End(e) => {
+                    path.pop();
+                    match (path.as_slice(), e.name().as_ref()) {
+                        ([], b"prop" | b"note") => children.end(e),
+                        ([tuv], b"seg") if tuv.as_slice() == b"tuv" => {}
+                        _ => push_seg_event(&mut tu.tuvs, &path, ev),
+                    }
+                }
+                Event::Empty(e) if path.is_empty() && e.name().as_ref() == b"tuv" => {
+                    tu.tuvs.push(Tuv { lang: tuv_lang(e), seg: Vec::new() });
+                }
+                _ => {
+                    children.text(ev);
+                    push_seg_event(&mut tu.tuvs, &path, ev);
+                }
+            }
+        }
+
+        tu.props = children.props;
+        tu.notes = children.notes;
+        tu.events = events;
+        tu
+    }
+
+    /// Replaces the raw events and rebuilds the typed view, keeping `span`, `namespaces` and the reported dates.
+    pub fn set_events(&mut self, events: Vec<Event<'static>>) {
+        let rebuilt = Tu::from_events(events);
+        self.attributes = rebuilt.attributes;
+        self.props = rebuilt.props;
+        self.notes = rebuilt.notes;
+        self.tuvs = rebuilt.tuvs;
+        self.events = rebuilt.events;
+    }
+
+    /// Value of the first `<prop>` with the given type.
+    pub fn prop(&self, prop_type: &str) -> Option<&str> {
+        self.props.iter().find(|p| p.prop_type == prop_type).map(|p| p.value.as_str())
+    }
+
+    /// `true` for a self-closing `<tu/>`.
+    pub fn is_empty_element(&self) -> bool {
+        matches!(self.events.first(), Some(Event::Empty(_)))
+    }
+}
+
+/// Props and notes go in front of the first `<tuv>`, indented like it.
+impl Element for Tu {
+    fn attributes(&self) -> &[(String, String)] {
+        &self.attributes
+    }
+
+    fn attributes_mut(&mut self) -> &mut Vec<(String, String)> {
+        &mut self.attributes
+    }
+
+    fn props_mut(&mut self) -> &mut Vec<Prop> {
+        &mut self.props
+    }
+
+    fn notes_mut(&mut self) -> &mut Vec<String> {
+        &mut self.notes
+    }
+
+    fn events_mut(&mut self) -> &mut Vec<Event<'static>> {
+        &mut self.events
+    }
+
+    fn insert_child(&mut self, mut child: Vec<Event<'static>>) {
+        // before the first <tuv>, or before </tu> when there is none
+        let at = self.events.iter()
+            .position(|ev| matches!(ev, Event::Start(e) | Event::Empty(e) if e.name().as_ref() == b"tuv"))
+            .unwrap_or(self.events.len().saturating_sub(1));
+        if let Some(Event::Text(t)) = at.checked_sub(1).map(|i| &self.events[i])
+            && is_xml_whitespace(t.as_ref())
+        {
+            child.push(Event::Text(t.clone()));
+        }
+
+        self.events.splice(at..at, child);
+    }
+}
+
+// events are part of the segment only while the innermost <tuv> child is <seg>
+fn push_seg_event(tuvs: &mut [Tuv], path: &[Vec<u8>], ev: &Event<'static>) {
+    if path.len() >= 2 && path[0] == b"tuv" && path[1] == b"seg"
+        && let Some(tuv) = tuvs.last_mut()
+    {
+        tuv.seg.push(ev.clone());
+    }
+}
+
+fn tuv_lang(e: &BytesStart) -> String {
+    attribute(e, "xml:lang").or_else(|| attribute(e, "lang")).unwrap_or_default()
+}
+
+pub(crate) fn attributes(e: &BytesStart) -> Vec<(String, String)> {
+    e.attributes()
+        .with_checks(false)
+        .filter_map(|a| a.ok())
+        .map(|a| {
+            let key = String::from_utf8_lossy(a.key.as_ref()).into_owned();
+            let value = match a.unescape_value() {
+                Ok(v) => v.into_owned(),
+                Err(_) => String::from_utf8_lossy(&a.value).into_owned(),
+            };
+            (key, value)
+        })
+        .collect()
+}
+
+/// A copy of `e` with its attributes replaced.
+pub(crate) fn with_attributes(e: &BytesStart, attributes: &[(String, String)]) -> BytesStart<'static> {
+    let mut start = BytesStart::new(String::from_utf8_lossy(e.name().as_ref()).into_owned());
+    start.extend_attributes(attributes.iter().map(|(key, value)| (key.as_str(), value.as_str())));
+    start
+}
+
+pub(crate) fn attribute(e: &BytesStart, name: &str) -> Option<String> {
+    attributes(e).into_iter().find(|(key, _)| key == name).map(|(_, value)| value)
+}
+
+/// `true` for the `<tu>` attributes holding a user id, which are compared ignoring case.
+pub(crate) fn is_user_id(attribute: &str) -> bool {
+    attribute == "creationid" || attribute == "changeid"
+}
+
+/// Character data carried by a text, CDATA or reference event.
+pub(crate) fn event_text<'a>(ev: &'a Event) -> Option<Cow<'a, str>> {
+    match ev {
+        Event::Text(t) => Some(String::from_utf8_lossy(t.as_ref())),
+        Event::CData(t) => Some(String::from_utf8_lossy(t.as_ref())),
+        Event::GeneralRef(r) => {
+            let name = String::from_utf8_lossy(r.as_ref());
+            if let Ok(Some(ch)) = r.resolve_char_ref() {
+                Some(Cow::Owned(ch.to_string()))
+            } else if let Some(resolved) = resolve_predefined_entity(&name) {
+                Some(Cow::Borrowed(resolved))
+            } else {
+                Some(Cow::Owned(format!("&{};", name)))
+            }
+        }
+        _ => None,
+    }
+}
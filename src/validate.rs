@@ -0,0 +1,394 @@
+use anyhow::{Context, Result};
+use chrono::NaiveDateTime;
+use quick_xml::events::{BytesStart, Event};
+use quick_xml::Reader;
+use std::fmt;
+use std::io::{self, BufRead, Read};
+
+use crate::date::{parse_date, DATE_ATTRIBUTES};
+use crate::io::open_input;
+use crate::tu::{attribute, Tu};
+
+const REQUIRED_HEADER_ATTRIBUTES: [&str; 7] =
+    ["creationtool", "creationtoolversion", "segtype", "o-tmf", "adminlang", "srclang", "datatype"];
+
+const SEGTYPES: [&str; 4] = ["block", "paragraph", "sentence", "phrase"];
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Severity {
+    Warning,
+    Error,
+}
+
+impl fmt::Display for Severity {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Severity::Warning => write!(f, "warning"),
+            Severity::Error => write!(f, "error"),
+        }
+    }
+}
+
+/// A broken TMX rule and the line and column, from 1, of the element that breaks it.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Finding {
+    pub severity: Severity,
+    pub line: u64,
+    /// In characters.
+    pub column: u64,
+    pub message: String,
+}
+
+impl fmt::Display for Finding {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}:{}: {}: {}", self.line, self.column, self.severity, self.message)
+    }
+}
+
+// counts lines and columns of what the XML reader consumes, so that the position of every event is known
+struct LineCounter<R> {
+    inner: R,
+    line: u64,
+    column: u64,
+}
+
+impl<R: BufRead> LineCounter<R> {
+    fn new(inner: R) -> Self {
+        LineCounter { inner, line: 1, column: 1 }
+    }
+
+    fn position(&self) -> Position {
+        Position { line: self.line, column: self.column }
+    }
+}
+
+impl<R: BufRead> Read for LineCounter<R> {
+    fn read(&mut self, out: &mut [u8]) -> io::Result<usize> {
+        let buf = self.fill_buf()?;
+        let len = buf.len().min(out.len());
+        out[..len].copy_from_slice(&buf[..len]);
+        self.consume(len);
+        Ok(len)
+    }
+}
+
+impl<R: BufRead> BufRead for LineCounter<R> {
+    fn fill_buf(&mut self) -> io::Result<&[u8]> {
+        self.inner.fill_buf()
+    }
+
+    fn consume(&mut self, amt: usize) {
+        // the consumed bytes are still at the start of the buffer
+        if let Ok(buf) = self.inner.fill_buf() {
+            for byte in &buf[..amt.min(buf.len())] {
+                match byte {
+                    b'\n' => {
+                        self.line += 1;
+                        self.column = 1;
+                    }
+                    // UTF-8 continuation bytes
+                    byte if byte & 0xc0 == 0x80 => {}
+                    _ => self.column += 1,
+                }
+            }
+        }
+        self.inner.consume(amt);
+    }
+}
+
+#[derive(Clone, Copy, Debug, Default)]
+struct Position {
+    line: u64,
+    column: u64,
+}
+
+struct TuState {
+    position: Position,
+    srclang: Option<String>,
+    tuvs: usize,
+    langs: Vec<String>,
+}
+
+struct TuvState {
+    position: Position,
+    segs: usize,
+}
+
+#[derive(Default)]
+struct Validator {
+    findings: Vec<Finding>,
+    root: Position,
+    // element names from the root down, innermost last
+    path: Vec<Vec<u8>>,
+    header: bool,
+    body: bool,
+    srclang: Option<String>,
+    tu: Option<TuState>,
+    tuv: Option<TuvState>,
+    // `i` and position of the <bpt> of the current <seg> without an <ept> yet, and the `i` of those closed
+    open_bpts: Vec<(String, Position)>,
+    closed_bpts: Vec<String>,
+}
+
+impl Validator {
+    fn report(&mut self, severity: Severity, position: Position, message: String) {
+        self.findings.push(Finding { severity, line: position.line, column: position.column, message });
+    }
+
+    fn start(&mut self, e: &BytesStart, position: Position) {
+        let name = e.name().as_ref().to_vec();
+        let parent = self.path.last().map(Vec::as_slice);
+
+        match (parent, name.as_slice()) {
+            (None, b"tmx") => {
+                self.root = position;
+                match attribute(e, "version") {
+                    Some(version) if version == "1.4" => {}
+                    Some(version) => {
+                        self.report(Severity::Warning, position, format!("TMX version {}, expected 1.4", version));
+                    }
+                    None => self.report(Severity::Error, position, "<tmx> without version".to_string()),
+                }
+            }
+            (None, name) => self.report(
+                Severity::Error,
+                position,
+                format!("root element <{}>, expected <tmx>", String::from_utf8_lossy(name)),
+            ),
+            (Some(b"tmx"), b"header") => {
+                self.header = true;
+                for required in REQUIRED_HEADER_ATTRIBUTES {
+                    if attribute(e, required).is_none() {
+                        self.report(Severity::Error, position, format!("<header> without {}", required));
+                    }
+                }
+                if let Some(segtype) = attribute(e, "segtype").filter(|segtype| !SEGTYPES.contains(&segtype.as_str())) {
+                    self.report(
+                        Severity::Error,
+                        position,
+                        format!("segtype '{}', expected block, paragraph, sentence or phrase", segtype),
+                    );
+                }
+                self.srclang = attribute(e, "srclang");
+                self.check_dates(e, position);
+            }
+            (Some(b"tmx"), b"body") => {
+                if !self.header {
+                    self.report(Severity::Error, position, "<body> before <header>".to_string());
+                }
+                self.body = true;
+            }
+            (Some(b"body"), b"tu") => {
+                self.check_dates(e, position);
+                self.tu = Some(TuState { position, srclang: attribute(e, "srclang"), tuvs: 0, langs: Vec::new() });
+            }
+            (Some(b"tu"), b"tuv") => {
+                self.check_dates(e, position);
+                let lang = match (attribute(e, "xml:lang"), attribute(e, "lang")) {
+                    (Some(lang), _) => Some(lang),
+                    (None, Some(lang)) => {
+                        self.report(Severity::Warning, position, "<tuv> with lang instead of xml:lang".to_string());
+                        Some(lang)
+                    }
+                    (None, None) => {
+                        self.report(Severity::Error, position, "<tuv> without xml:lang".to_string());
+                        None
+                    }
+                };
+                if let Some(tu) = &mut self.tu {
+                    tu.tuvs += 1;
+                    tu.langs.extend(lang);
+                }
+                self.tuv = Some(TuvState { position, segs: 0 });
+            }
+            (Some(b"tuv"), b"seg") => {
+                if let Some(tuv) = &mut self.tuv {
+                    tuv.segs += 1;
+                }
+                self.open_bpts.clear();
+                self.closed_bpts.clear();
+            }
+            (_, b"bpt") if self.in_seg() => match attribute(e, "i") {
+                Some(i) if self.open_bpts.iter().any(|(open, _)| *open == i) || self.closed_bpts.contains(&i) => {
+                    self.report(Severity::Error, position, format!("second <bpt> with i=\"{}\" in the segment", i));
+                }
+                Some(i) => self.open_bpts.push((i, position)),
+                None => self.report(Severity::Error, position, "<bpt> without i".to_string()),
+            },
+            (_, b"ept") if self.in_seg() => match attribute(e, "i") {
+                Some(i) => match self.open_bpts.iter().position(|(open, _)| *open == i) {
+                    Some(open) => {
+                        self.open_bpts.remove(open);
+                        self.closed_bpts.push(i);
+                    }
+                    None => self.report(Severity::Error, position, format!("<ept i=\"{}\"> without a <bpt> before it", i)),
+                },
+                None => self.report(Severity::Error, position, "<ept> without i".to_string()),
+            },
+            _ => {}
+        }
+
+        self.path.push(name);
+    }
+
+    fn end(&mut self) {
+        let Some(name) = self.path.pop() else { return };
+        let parent = self.path.last().map(Vec::as_slice);
+
+        match (parent, name.as_slice()) {
+            (None, b"tmx") => {
+                let position = self.root;
+                if !self.header {
+                    self.report(Severity::Error, position, "<tmx> without <header>".to_string());
+                }
+                if !self.body {
+                    self.report(Severity::Error, position, "<tmx> without <body>".to_string());
+                }
+            }
+            (Some(b"body"), b"tu") => {
+                let Some(tu) = self.tu.take() else { return };
+                if tu.tuvs == 0 {
+                    self.report(Severity::Error, tu.position, "<tu> without <tuv>".to_string());
+                    return;
+                }
+                match tu.srclang.as_deref().or(self.srclang.as_deref()) {
+                    Some(srclang) if srclang != "*all*" && !tu.langs.iter().any(|lang| same_lang(lang, srclang)) => {
+                        self.report(Severity::Error, tu.position, format!("<tu> without a <tuv> in srclang {}", srclang));
+                    }
+                    _ => {}
+                }
+            }
+            (Some(b"tu"), b"tuv") => {
+                let Some(tuv) = self.tuv.take() else { return };
+                if tuv.segs != 1 {
+                    self.report(
+                        Severity::Error,
+                        tuv.position,
+                        format!("<tuv> with {} <seg>, expected exactly one", tuv.segs),
+                    );
+                }
+            }
+            (Some(b"tuv"), b"seg") => {
+                for (i, position) in std::mem::take(&mut self.open_bpts) {
+                    self.report(Severity::Error, position, format!("<bpt i=\"{}\"> without an <ept> after it", i));
+                }
+            }
+            _ => {}
+        }
+    }
+
+    fn in_seg(&self) -> bool {
+        self.path.iter().any(|name| name == b"seg")
+    }
+
+    fn check_dates(&mut self, e: &BytesStart, position: Position) {
+        for name in DATE_ATTRIBUTES {
+            let Some(value) = attribute(e, name) else { continue };
+            if value.len() == 16 && NaiveDateTime::parse_from_str(&value, "%Y%m%dT%H%M%SZ").is_ok() {
+                continue;
+            }
+            match parse_date(&value) {
+                Ok(_) => self.report(
+                    Severity::Warning,
+                    position,
+                    format!("{} '{}' is not in the TMX format YYYYMMDDThhmmssZ", name, value),
+                ),
+                Err(_) => self.report(Severity::Error, position, format!("{} '{}' is not a date", name, value)),
+            }
+        }
+    }
+}
+
+// `de` is the same language as `de-DE`, ignoring case
+fn same_lang(a: &str, b: &str) -> bool {
+    let (short, long) = if a.len() <= b.len() { (a, b) } else { (b, a) };
+    long.get(..short.len()).is_some_and(|prefix| prefix.eq_ignore_ascii_case(short))
+        && (long.len() == short.len() || long.as_bytes()[short.len()] == b'-')
+}
+
+/// Checks `input` against the rules of TMX 1.4b: the required `<header>` attributes, a `<tuv>` in every `<tu>`, an
+/// `xml:lang` and exactly one `<seg>` in every `<tuv>`, `<bpt>` and `<ept>` paired by `i`, dates in the TMX format
+/// and a `<tuv>` in the `srclang` of every `<tu>`, unless it is `*all*`. Malformed XML ends the check with an error.
+pub fn validate(input: &str) -> Result<Vec<Finding>> {
+    let mut reader = Reader::from_reader(LineCounter::new(open_input(input).context("Cannot open input file")?));
+
+    let mut validator = Validator::default();
+    let mut buf = Vec::new();
+    // the reader consumes the `<` after a text along with it
+    let mut after_text = false;
+    loop {
+        let mut position = reader.get_ref().position();
+        if after_text {
+            position.column -= 1;
+        }
+        let event = reader.read_event_into(&mut buf);
+        after_text = matches!(event, Ok(Event::Text(_)));
+        match event {
+            Ok(Event::Start(e)) => validator.start(&e, position),
+            Ok(Event::Empty(e)) => {
+                validator.start(&e, position);
+                validator.end();
+            }
+            Ok(Event::End(_)) => validator.end(),
+            Ok(Event::Eof) => break,
+            Ok(_) => {}
+            Err(quick_xml::Error::Io(e)) => return Err(anyhow::Error::new(e).context("Cannot read input file")),
+            Err(e) => {
+                validator.report(Severity::Error, position, format!("malformed XML: {}", e));
+                break;
+            }
+        }
+        buf.clear();
+    }
+
+    Ok(validator.findings)
+}
+
+/// Checks a single TU against the rules [`validate`] checks for TUs; `srclang` is the one of the header.
+pub fn validate_tu(tu: &Tu, srclang: Option<&str>) -> Vec<(Severity, String)> {
+    let mut validator = Validator {
+        path: vec![b"tmx".to_vec(), b"body".to_vec()],
+        srclang: srclang.map(str::to_string),
+        ..Default::default()
+    };
+    for ev in &tu.events {
+        match ev {
+            Event::Start(e) => validator.start(e, Position::default()),
+            Event::Empty(e) => {
+                validator.start(e, Position::default());
+                validator.end();
+            }
+            Event::End(_) => validator.end(),
+            _ => {}
+        }
+    }
+
+    validator.findings.into_iter().map(|finding| (finding.severity, finding.message)).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pretty_assertions::assert_eq;
+
+    #[test]
+    fn test_validate() {
+        let findings: Vec<String> = validate("test-data/validate/test.tmx").unwrap().iter().map(ToString::to_string).collect();
+        assert_eq!(findings, vec![
+            "2:5: error: <header> without creationtoolversion",
+            "2:5: error: segtype 'line', expected block, paragraph, sentence or phrase",
+            "4:9: warning: creationdate '2021-03-04T10:00:00' is not in the TMX format YYYYMMDDThhmmssZ",
+            "8:9: error: changedate 'yesterday' is not a date",
+            "9:13: error: <tuv> without xml:lang",
+            "8:9: error: <tu> without a <tuv> in srclang de-DE",
+            "11:9: error: <tu> without <tuv>",
+            "13:13: error: <tuv> with 2 <seg>, expected exactly one",
+            "16:70: error: <ept i=\"2\"> without a <bpt> before it",
+            "16:40: error: <bpt i=\"1\"> without an <ept> after it",
+            "17:94: error: second <bpt> with i=\"1\" in the segment",
+            "21:9: error: malformed XML: ill-formed document: expected `</tuv>`, but `</tu>` was found",
+        ]);
+
+        assert_eq!(validate("test-data/trim/test.tmx").unwrap(), vec![]);
+    }
+}
@@ -0,0 +1,67 @@
+use anyhow::Result;
+use quick_xml::events::Event;
+use quick_xml::Writer;
+use std::io::Write;
+
+use crate::reader::TmxItem;
+use crate::tu::Tu;
+use crate::whitespace::is_xml_whitespace;
+
+/// Streaming TMX writer, the counterpart of [`crate::reader::TmxReader`].
+pub struct TmxWriter<W: Write> {
+    writer: Writer<W>,
+    drop_whitespace: bool,
+}
+
+impl<W: Write> TmxWriter<W> {
+    pub fn new(inner: W) -> Self {
+        TmxWriter { writer: Writer::new(inner), drop_whitespace: false }
+    }
+
+    pub fn write_event<'a>(&mut self, ev: impl Into<Event<'a>>) -> Result<()> {
+        let ev = ev.into();
+        if std::mem::take(&mut self.drop_whitespace)
+            && let Event::Text(ref t) = ev
+            && is_xml_whitespace(t.as_ref())
+        {
+            return Ok(());
+        }
+
+        self.writer.write_event(ev)?;
+        Ok(())
+    }
+
+    pub fn write_tu(&mut self, tu: &Tu) -> Result<()> {
+        for ev in &tu.events {
+            self.write_event(ev.borrow())?;
+        }
+        Ok(())
+    }
+
+    pub fn write_item(&mut self, item: &TmxItem) -> Result<()> {
+        match item {
+            TmxItem::Event(ev) => self.write_event(ev.borrow()),
+            TmxItem::Tu(tu) => self.write_tu(tu),
+        }
+    }
+
+    /// Writes bytes copied verbatim from an input, e.g. a `<tu>` located by its span.
+    pub fn write_raw(&mut self, bytes: &[u8]) -> Result<()> {
+        self.drop_whitespace = false;
+        self.writer.get_mut().write_all(bytes)?;
+        Ok(())
+    }
+
+    /// Drops the whitespace that directly follows, e.g. the indentation after a skipped `<tu>`.
+    pub fn drop_next_whitespace(&mut self) {
+        self.drop_whitespace = true;
+    }
+
+    pub fn get_mut(&mut self) -> &mut Writer<W> {
+        &mut self.writer
+    }
+
+    pub fn into_inner(self) -> W {
+        self.writer.into_inner()
+    }
+}